@@ -26,6 +26,9 @@ SECTIONS {
         *(.text.trampoline.entry);
         *(.text.trampoline);
         . = ALIGN(4K);
+        ssigreturn = .;
+        *(.text.sigreturn);
+        . = ALIGN(4K);
 
         *(.text .text.*)
         etext = .;
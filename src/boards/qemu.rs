@@ -1,6 +1,13 @@
 pub const MMIO: &[(usize, usize)] = &[
     (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
+    (0x0c00_0000, 0x40_0000), // PLIC in virt machine
+    (0x1000_0000, 0x00_1000), // UART in virt machine
     (0x1000_1000, 0x00_1000), // Virtio Block in virt machine
 ];
 
+pub const PLIC_BASE: usize = 0x0c00_0000;
+pub const UART_BASE: usize = 0x1000_0000;
+/// 串口在 PLIC 上的中断源
+pub const UART_IRQ: usize = 10;
+
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
@@ -12,7 +12,13 @@ pub const GUARD_PAGE_SIZE: usize = 4 * PAGE_SIZE;
 pub const USER_STACK_SIZE: usize = 0x4000;
 /// 跳板地址
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
+/// 信号处理函数的返回地址，只映射在用户空间
+pub const SIGRETURN_TRAMPOLINE: usize = TRAMPOLINE - PAGE_SIZE;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - 0xF0 * PAGE_SIZE;
+/// 位置无关可执行文件的加载地址
+pub const PIE_BASE: usize = 0x10_0000;
+/// 动态链接器的加载地址
+pub const INTERP_BASE: usize = 0x20_0000_0000;
 pub const MEMORY_END: usize = 0x8800_0000;
 
 /// 平台时钟频率
@@ -1,4 +1,5 @@
 pub mod block;
+pub mod plic;
 use dtb_walker::{self, utils::indent, Dtb, DtbObj, WalkOperation};
 
 const INDENT_WIDTH: usize = 4;
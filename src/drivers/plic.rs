@@ -0,0 +1,50 @@
+use log::warn;
+
+use crate::{
+    board::{PLIC_BASE, UART_BASE, UART_IRQ},
+    fs::stdio::TTY,
+};
+
+const ENABLE_OFFSET: usize = 0x2000;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+
+/// 硬件线程 `hartid` 的 S 态上下文
+fn context(hartid: usize) -> usize {
+    hartid * 2 + 1
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { ((PLIC_BASE + offset) as *const u32).read_volatile() }
+}
+
+fn write(offset: usize, value: u32) {
+    unsafe { ((PLIC_BASE + offset) as *mut u32).write_volatile(value) }
+}
+
+/// 外部中断只路由到 `hartid`，需要在映射内核地址空间后调用
+pub fn init(hartid: usize) {
+    let context = context(hartid);
+    write(UART_IRQ * 4, 1);
+    write(
+        ENABLE_OFFSET + context * 0x80 + UART_IRQ / 32 * 4,
+        1 << (UART_IRQ % 32),
+    );
+    write(CONTEXT_OFFSET + context * 0x1000, 0);
+    // 打开串口的接收中断，输入仍然通过 SBI 读取
+    unsafe { ((UART_BASE + 1) as *mut u8).write_volatile(1) };
+}
+
+/// 认领并处理所有待处理的外部中断
+pub fn handle_external(hartid: usize) {
+    let claim = CONTEXT_OFFSET + context(hartid) * 0x1000 + 4;
+    loop {
+        let irq = read(claim) as usize;
+        match irq {
+            0 => break,
+            // 读取输入并向前台进程组发送控制字符产生的信号
+            UART_IRQ => TTY.poll(),
+            irq => warn!("unexpected external interrupt {}", irq),
+        }
+        write(claim, irq as u32);
+    }
+}
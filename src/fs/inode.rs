@@ -2,16 +2,22 @@ use crate::{
     drivers::block::BLOCK_DEVICE,
     mm::page_table::BufferHandle,
     println,
+    syscall::errno::{ENOENT, ENOSPC},
     task::{
+        cred::{Access, Credentials},
+        loader::read_program,
+        preempt::{preempt_point, PreemptMutex},
         process::{Process, ProcessControlBlock},
+        rlimit::ResourceLimits,
         tcb::Task,
     },
 };
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use anyhow::{anyhow, Result};
 use bitflags::bitflags;
 use easy_fs::{EasyFileSystem, FileType, Inode};
+use log::warn;
 use spin::{Lazy, Mutex};
-use xmas_elf::ElfFile;
 
 use super::{File, FileFlags};
 
@@ -29,9 +35,49 @@ pub fn list_apps() {
     println!("---------");
 }
 
+pub const S_ISUID: u32 = 0o4000;
+pub const S_ISGID: u32 = 0o2000;
+pub const S_IXGRP: u32 = 0o010;
+/// 新建文件的权限
+const CREATE_MODE: u32 = 0o644;
+
+/// 文件的所有者和权限
+#[derive(Debug, Clone, Copy)]
+pub struct FileMetadata {
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+}
+
+/// 文件系统中已有的文件属于 root，所有用户可读可执行
+const DEFAULT_METADATA: FileMetadata = FileMetadata {
+    uid: 0,
+    gid: 0,
+    mode: 0o755,
+};
+
+/// easy-fs 不保存所有者和权限，由内核按文件名在内存中维护
+static METADATA: Mutex<BTreeMap<String, FileMetadata>> = Mutex::new(BTreeMap::new());
+
+/// 文件不存在时返回 `None`
+pub fn file_metadata(path: &str) -> Option<FileMetadata> {
+    ROOT_INODE.find(path)?;
+    Some(
+        METADATA
+            .lock()
+            .get(path)
+            .copied()
+            .unwrap_or(DEFAULT_METADATA),
+    )
+}
+
+pub fn set_file_metadata(path: &str, metadata: FileMetadata) {
+    METADATA.lock().insert(String::from(path), metadata);
+}
+
 pub struct OSInode {
     perm: FileFlags,
-    inner: Mutex<OSInodeInner>,
+    inner: PreemptMutex<OSInodeInner>,
 }
 
 pub struct OSInodeInner {
@@ -43,20 +89,24 @@ impl OSInode {
     pub fn new(perm: FileFlags, inode: Arc<Inode>) -> Self {
         Self {
             perm,
-            inner: Mutex::new(OSInodeInner::new(inode)),
+            inner: PreemptMutex::new(OSInodeInner::new(inode)),
         }
     }
     pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.lock();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = {
+                let mut inner = self.inner.lock();
+                let len = inner.inode.read_at(inner.offset, &mut buffer);
+                inner.offset += len;
+                len
+            };
             if len == 0 {
                 break;
             }
-            inner.offset += len;
             v.extend_from_slice(&buffer[..len]);
+            preempt_point();
         }
         v
     }
@@ -77,28 +127,39 @@ impl File for OSInode {
         self.perm.contains(FileFlags::W)
     }
 
+    /// 每读写一个缓冲区后释放锁，在安全点被抢占
     fn read(&self, mut buffer_handle: BufferHandle) -> usize {
-        let mut inner = self.inner.lock();
-        let base_offset = inner.offset;
+        let mut total = 0;
         for buffer in buffer_handle.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, buffer);
+            let read_size = {
+                let mut inner = self.inner.lock();
+                let read_size = inner.inode.read_at(inner.offset, buffer);
+                inner.offset += read_size;
+                read_size
+            };
             if read_size == 0 {
                 break;
             }
-            inner.offset += read_size;
+            total += read_size;
+            preempt_point();
         }
-        inner.offset - base_offset
+        total
     }
 
     fn write(&self, buffer_handle: BufferHandle) -> usize {
-        let mut inner = self.inner.lock();
-        let base_offset = inner.offset;
+        let mut total = 0;
         for buffer in &buffer_handle.buffers {
-            let wrtie_size = inner.inode.write_at(inner.offset, buffer);
+            let wrtie_size = {
+                let mut inner = self.inner.lock();
+                let wrtie_size = inner.inode.write_at(inner.offset, buffer);
+                inner.offset += wrtie_size;
+                wrtie_size
+            };
             assert_eq!(wrtie_size, buffer.len());
-            inner.offset += wrtie_size;
+            total += wrtie_size;
+            preempt_point();
         }
-        inner.offset - base_offset
+        total
     }
 }
 
@@ -116,6 +177,8 @@ bitflags! {
         const CREATE = 1 << 2;
         ///Clear file and return an empty one
         const TRUNC = 1 << 3;
+        ///Close on exec
+        const CLOEXEC = 1 << 4;
     }
 }
 
@@ -141,34 +204,62 @@ impl OpenFlags {
     }
 }
 
-pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// 以 `cred` 的身份打开文件，新建的文件属于 `cred` 的有效用户和组。
+/// 根目录对所有用户可写
+pub fn open_file(path: &str, flags: OpenFlags, cred: &Credentials) -> Result<Arc<OSInode>> {
     let perm = flags.get_perm();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(path) {
-            inode.clear();
-            Some(Arc::new(OSInode::new(perm, inode)))
-        } else {
-            ROOT_INODE
-                .create(path, FileType::File)
-                .map(|inode| Arc::new(OSInode::new(perm, inode)))
+    let Some(inode) = ROOT_INODE.find(path) else {
+        if !flags.contains(OpenFlags::CREATE) {
+            return Err(ENOENT.with(anyhow!("no such file {}", path)));
         }
-    } else {
-        ROOT_INODE.find(path).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear()
-            }
-            Arc::new(OSInode::new(perm, inode))
-        })
+        let inode = ROOT_INODE
+            .create(path, FileType::File)
+            .ok_or_else(|| ENOSPC.with(anyhow!("failed to create {}", path)))?;
+        set_file_metadata(
+            path,
+            FileMetadata {
+                uid: cred.euid(),
+                gid: cred.egid(),
+                mode: CREATE_MODE,
+            },
+        );
+        return Ok(Arc::new(OSInode::new(perm, inode)));
+    };
+    let truncate = flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC);
+    let mut access = Access::empty();
+    access.set(Access::R, perm.contains(FileFlags::R));
+    access.set(Access::W, perm.contains(FileFlags::W) || truncate);
+    cred.check_access(&file_metadata(path).unwrap_or(DEFAULT_METADATA), access)?;
+    if truncate {
+        inode.clear();
     }
+    Ok(Arc::new(OSInode::new(perm, inode)))
 }
 
-pub fn open_app(path: &str, args: &str) -> Option<(Process, Task)> {
-    if let Some(app_inode) = open_file(path, OpenFlags::RDONLY) {
-        let app_data = app_inode.read_all();
-        let elf = ElfFile::new(app_data.as_slice()).unwrap();
-        let process = ProcessControlBlock::from_elf(elf, args);
-        Some(process)
-    } else {
-        None
-    }
+/// 内核读取文件时使用，不检查权限
+pub fn read_file(path: &str) -> Option<Arc<OSInode>> {
+    ROOT_INODE
+        .find(path)
+        .map(|inode| Arc::new(OSInode::new(FileFlags::R, inode)))
+}
+
+/// 以 `cred` 的身份运行程序，新进程的身份按程序的 setuid 和 setgid 位改变，
+/// 用户栈按 `rlimits` 的 `RLIMIT_STACK` 分配
+pub fn open_app(
+    path: &str,
+    args: &[String],
+    envs: &[String],
+    cred: &Credentials,
+    rlimits: &ResourceLimits,
+) -> Option<(Process, Task)> {
+    read_program(path, args.to_vec(), cred)
+        .and_then(|(app_data, args, metadata)| {
+            let (process, task) = ProcessControlBlock::from_elf(&app_data, &args, envs, rlimits)?;
+            let mut cred = cred.clone();
+            cred.exec(&metadata);
+            process.inner.write().cred = cred;
+            Ok((process, task))
+        })
+        .inspect_err(|err| warn!("load {} failed: {}", path, err))
+        .ok()
 }
@@ -10,6 +10,10 @@ pub type FileBox = Arc<dyn File + Send + Sync>;
 pub trait File {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
+    /// 是否为终端，终端支持 `ioctl` 和作业控制
+    fn is_tty(&self) -> bool {
+        false
+    }
     /// 读取文件到 `BufferHandle` ，返回读取长度
     fn read(&self, buffer_handle: BufferHandle) -> usize;
     /// 写入 `BufferHandle` 到文件，返回写入长度
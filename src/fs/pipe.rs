@@ -1,27 +1,31 @@
 use alloc::sync::{Arc, Weak};
 use anyhow::Result;
 
-use spin::Mutex;
-
-use crate::{mm::page_table::BufferHandle, task::processor::yield_};
+use crate::{
+    mm::page_table::BufferHandle,
+    task::{
+        preempt::{need_resched, preempt_point, PreemptMutex},
+        processor::{current_killed, yield_},
+    },
+};
 
 use super::File;
 
 pub struct Pipe {
     readable: bool,
     writable: bool,
-    buffer: Arc<Mutex<PipeBuffer>>,
+    buffer: Arc<PreemptMutex<PipeBuffer>>,
 }
 
 impl Pipe {
-    pub fn read_end(buffer: Arc<Mutex<PipeBuffer>>) -> Self {
+    pub fn read_end(buffer: Arc<PreemptMutex<PipeBuffer>>) -> Self {
         Self {
             readable: true,
             writable: false,
             buffer,
         }
     }
-    pub fn write_end(buffer: Arc<Mutex<PipeBuffer>>) -> Self {
+    pub fn write_end(buffer: Arc<PreemptMutex<PipeBuffer>>) -> Self {
         Self {
             readable: false,
             writable: true,
@@ -95,12 +99,18 @@ impl File for Pipe {
         let mut read_len = 0;
         let mut pipe_buffer = self.buffer.lock();
         for x in buffer_handle.into_iter() {
+            // 大量读写时在不持有锁的安全点被抢占
+            if need_resched() {
+                drop(pipe_buffer);
+                preempt_point();
+                pipe_buffer = self.buffer.lock();
+            }
             loop {
                 if let Some(byte) = pipe_buffer.read() {
                     read_len += 1;
                     *x = byte;
                     break;
-                } else if pipe_buffer.all_write_ends_closed() {
+                } else if pipe_buffer.all_write_ends_closed() || current_killed() {
                     return read_len;
                 } else {
                     drop(pipe_buffer);
@@ -118,10 +128,17 @@ impl File for Pipe {
         let mut writed_len = 0;
         let mut pipe_buffer = self.buffer.lock();
         for x in buffer_handle.into_iter() {
+            if need_resched() {
+                drop(pipe_buffer);
+                preempt_point();
+                pipe_buffer = self.buffer.lock();
+            }
             loop {
                 if pipe_buffer.write(*x).is_ok() {
                     writed_len += 1;
                     break;
+                } else if current_killed() {
+                    return writed_len;
                 } else {
                     drop(pipe_buffer);
                     yield_();
@@ -134,7 +151,7 @@ impl File for Pipe {
 }
 
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(Mutex::new(PipeBuffer::new()));
+    let buffer = Arc::new(PreemptMutex::new(PipeBuffer::new()));
     let read_end = Arc::new(Pipe::read_end(buffer.clone()));
     let write_end = Arc::new(Pipe::write_end(buffer.clone()));
     buffer.lock().set_write_end(&write_end);
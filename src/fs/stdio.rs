@@ -1,5 +1,155 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicIsize, Ordering};
+
+use anyhow::Result;
+use spin::Mutex;
+
 use super::File;
-use crate::{mm::page_table::BufferHandle, print, sbi::console_getchar, task::processor::yield_};
+use crate::{
+    config::CLOCK_FREQ,
+    mm::page_table::BufferHandle,
+    print,
+    sbi::console_getchar,
+    syscall::errno::{EIO, ERESTARTSYS},
+    task::{
+        process::{is_orphaned_group, process_group},
+        processor::{blocking_current, current_task},
+        signal::{SignalFlags, SIG_IGN},
+        tcb::TaskControlBlock,
+        tigger::{Interruptible, Timer},
+    },
+    timer::{add_timer, get_time, TimerHandle},
+};
+
+/// 有进程等待输入时读取控制台的间隔（毫秒）
+const POLL_INTERVAL_MS: usize = 10;
+/// 终端被会话控制时读取控制字符的间隔，前台进程组不读取终端时也能收到信号
+const SIGNAL_POLL_INTERVAL: usize = CLOCK_FREQ / 10;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_Z: u8 = 0x1a;
+const CTRL_BACKSLASH: u8 = 0x1c;
+
+/// 控制台终端，同一时刻最多是一个会话的控制终端
+pub struct Terminal {
+    /// 以该终端为控制终端的会话，为0表示没有
+    session: AtomicIsize,
+    /// 前台进程组
+    foreground: AtomicIsize,
+    /// 已经从控制台读取但尚未被进程取走的输入
+    input: Mutex<VecDeque<u8>>,
+    /// 终端有会话时定期读取控制台的定时器
+    poller: Mutex<Option<TimerHandle>>,
+}
+
+pub static TTY: Terminal = Terminal::new();
+
+impl Terminal {
+    const fn new() -> Self {
+        Self {
+            session: AtomicIsize::new(0),
+            foreground: AtomicIsize::new(0),
+            input: Mutex::new(VecDeque::new()),
+            poller: Mutex::new(None),
+        }
+    }
+
+    pub fn session(&self) -> isize {
+        self.session.load(Ordering::Relaxed)
+    }
+
+    pub fn foreground(&self) -> isize {
+        self.foreground.load(Ordering::Relaxed)
+    }
+
+    pub fn set_foreground(&self, pgid: isize) {
+        self.foreground.store(pgid, Ordering::Relaxed);
+    }
+
+    /// 成为会话 `sid` 的控制终端，前台进程组为 `pgid`
+    pub fn attach(&self, sid: isize, pgid: isize) {
+        self.session.store(sid, Ordering::Relaxed);
+        self.set_foreground(pgid);
+        let mut poller = self.poller.lock();
+        if poller.is_none() {
+            *poller = Some(add_timer(
+                get_time() + SIGNAL_POLL_INTERVAL,
+                SIGNAL_POLL_INTERVAL,
+                Arc::new(|| TTY.poll()),
+            ));
+        }
+    }
+
+    /// 会话首进程退出时挂断终端，前台进程组收到 `SIGHUP` 和 `SIGCONT`
+    pub fn hangup(&self, sid: isize) {
+        if self
+            .session
+            .compare_exchange(sid, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        if let Some(poller) = self.poller.lock().take() {
+            poller.cancel();
+        }
+        let pgid = self.foreground.swap(0, Ordering::Relaxed);
+        for process in process_group(pgid) {
+            process.send_signal(SignalFlags::SIGHUP);
+            process.send_signal(SignalFlags::SIGCONT);
+        }
+    }
+
+    /// 读取控制台的所有输入，控制字符向前台进程组发送信号
+    pub fn poll(&self) {
+        let mut input = self.input.lock();
+        loop {
+            let signal = match console_getchar() {
+                0 => break,
+                c => match c as u8 {
+                    CTRL_C => SignalFlags::SIGINT,
+                    CTRL_Z => SignalFlags::SIGTSTP,
+                    CTRL_BACKSLASH => SignalFlags::SIGQUIT,
+                    c => {
+                        input.push_back(c);
+                        continue;
+                    }
+                },
+            };
+            for process in process_group(self.foreground()) {
+                process.send_signal(signal);
+            }
+        }
+    }
+
+    pub fn getchar(&self) -> Option<u8> {
+        self.poll();
+        self.input.lock().pop_front()
+    }
+
+    /// 后台进程组访问控制终端时向其发送 `signal`（`SIGTTIN` 或 `SIGTTOU`）使其停止，
+    /// 返回 `ERESTARTSYS` 时系统调用在进程继续运行后重新执行
+    pub fn job_control(&self, task: &TaskControlBlock, signal: SignalFlags) -> Result<()> {
+        let process = &task.process;
+        let pgid = process.get_pgid();
+        if process.get_sid() != self.session() || pgid == self.foreground() {
+            return Ok(());
+        }
+        let ignored = process.inner.read().signal.lock().actions[signal.signum()].handler
+            == SIG_IGN
+            || task.shared.sigmask.lock().contains(signal);
+        if ignored && signal == SignalFlags::SIGTTOU {
+            // 忽略 `SIGTTOU` 的后台进程可以修改终端
+            return Ok(());
+        }
+        if ignored || is_orphaned_group(pgid) {
+            return Err(EIO.with("background process group cannot access the terminal"));
+        }
+        for process in process_group(pgid) {
+            process.send_signal(signal);
+        }
+        Err(ERESTARTSYS.with("background process group accessed the terminal"))
+    }
+}
 
 pub struct Stdin;
 
@@ -10,14 +160,21 @@ impl File for Stdin {
     fn writable(&self) -> bool {
         false
     }
+    fn is_tty(&self) -> bool {
+        true
+    }
+    /// 被终止或收到信号时不读取数据直接返回，由 `sys_read` 决定返回值
     fn read(&self, mut buffer_handle: BufferHandle) -> usize {
+        let task = current_task();
         let ch: u8 = loop {
-            let c = console_getchar();
-            if c == 0 {
-                yield_();
-            } else {
-                break c as u8;
+            if let Some(c) = TTY.getchar() {
+                break c;
+            }
+            if task.is_killed() || task.shared.has_signal(&task.process.shared) {
+                return 0;
             }
+            // 控制台没有输入中断，只在有进程等待时定期轮询
+            blocking_current(Interruptible::new(&task, Timer::new(POLL_INTERVAL_MS)));
         };
         buffer_handle.write(&[ch]);
         1
@@ -38,6 +195,10 @@ impl File for Stdout {
         true
     }
 
+    fn is_tty(&self) -> bool {
+        true
+    }
+
     fn read(&self, _buffer_handle: BufferHandle) -> usize {
         panic!("Cannot read from stdout!");
     }
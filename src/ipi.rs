@@ -0,0 +1,121 @@
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use core::{
+    arch::asm,
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use riscv::register::sip;
+use spin::Mutex;
+
+use crate::{
+    config::{NUM_HARTS, PAGE_SIZE},
+    mm::address::VirtAddr,
+    sbi::get_hartid,
+    task::scheduler::get_processor,
+};
+
+/// 超过该页数时刷新整个 TLB
+const FLUSH_ALL_THRESHOLD: usize = 32;
+
+pub enum IpiMessage {
+    /// 唤醒目标处理器轮询等待队列
+    Wakeup,
+    /// 在目标处理器上执行函数
+    Call(Box<dyn FnOnce() + Send>),
+    /// 刷新 `[start, start + len)` 的 TLB，完成后将 `ack` 减1
+    TlbFlush {
+        start: VirtAddr,
+        len: usize,
+        ack: Arc<AtomicUsize>,
+    },
+}
+
+static IPI_QUEUES: [Mutex<VecDeque<IpiMessage>>; NUM_HARTS] =
+    [const { Mutex::new(VecDeque::new()) }; NUM_HARTS];
+
+/// 向 `mask` 中的所有硬件线程发送处理器间中断
+fn send_ipi_mask(mask: usize) {
+    if mask != 0 {
+        sbi_rt::send_ipi(sbi_rt::HartMask::from_mask_base(mask, 0));
+    }
+}
+
+pub fn send_ipi(hartid: usize, message: IpiMessage) {
+    IPI_QUEUES[hartid].lock().push_back(message);
+    send_ipi_mask(1 << hartid);
+}
+
+/// 让所有处理器立即检查等待队列中被唤醒的任务
+pub fn wakeup_all() {
+    get_processor().notify_wait();
+    let current = get_hartid();
+    for hartid in (0..NUM_HARTS).filter(|&hartid| hartid != current) {
+        send_ipi(hartid, IpiMessage::Wakeup);
+    }
+}
+
+/// 在 `hartid` 上异步执行 `f`，`f` 在中断处理中运行，不能阻塞或切换任务
+pub fn call_on(hartid: usize, f: impl FnOnce() + Send + 'static) {
+    send_ipi(hartid, IpiMessage::Call(Box::new(f)));
+}
+
+fn sfence_vma(start: VirtAddr, len: usize) {
+    let start = usize::from(start) & !(PAGE_SIZE - 1);
+    let pages = len.div_ceil(PAGE_SIZE);
+    unsafe {
+        if pages > FLUSH_ALL_THRESHOLD {
+            asm!("sfence.vma");
+        } else {
+            for page in 0..pages {
+                asm!("sfence.vma {}, zero", in(reg) start + page * PAGE_SIZE);
+            }
+        }
+    }
+}
+
+/// 刷新 `mask` 中所有硬件线程上 `[start, start + len)` 的 TLB，等待全部完成后返回
+pub fn tlb_shootdown(mask: usize, start: VirtAddr, len: usize) {
+    let mask = mask & !(1 << get_hartid());
+    if mask == 0 {
+        return;
+    }
+    let ack = Arc::new(AtomicUsize::new(mask.count_ones() as usize));
+    for hartid in (0..NUM_HARTS).filter(|hartid| mask & (1 << hartid) != 0) {
+        IPI_QUEUES[hartid].lock().push_back(IpiMessage::TlbFlush {
+            start,
+            len,
+            ack: ack.clone(),
+        });
+    }
+    send_ipi_mask(mask);
+    // 等待期间处理发给自己的消息，避免两个处理器相互等待
+    while ack.load(Ordering::Acquire) != 0 {
+        handle_ipi();
+        hint::spin_loop();
+    }
+}
+
+/// 处理当前硬件线程上所有待处理的处理器间消息
+pub fn handle_ipi() {
+    unsafe { sip::clear_ssoft() };
+    let queue = &IPI_QUEUES[get_hartid()];
+    loop {
+        let message = queue.lock().pop_front();
+        match message {
+            Some(IpiMessage::Wakeup) => get_processor().notify_wait(),
+            Some(IpiMessage::Call(f)) => f(),
+            Some(IpiMessage::TlbFlush { start, len, ack }) => {
+                sfence_vma(start, len);
+                ack.fetch_sub(1, Ordering::Release);
+            }
+            None => break,
+        }
+    }
+}
+
+/// 关中断时检查是否有未处理的处理器间中断
+pub fn handle_pending_ipi() {
+    if sip::read().ssoft() {
+        handle_ipi();
+    }
+}
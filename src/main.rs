@@ -15,6 +15,7 @@ mod console;
 mod config;
 mod drivers;
 mod fs;
+mod ipi;
 mod lang_items;
 mod mm;
 mod sbi;
@@ -26,6 +27,7 @@ mod timer;
 mod tools;
 mod trap;
 
+use log::info;
 use sbi::get_hartid;
 
 use crate::{
@@ -33,11 +35,7 @@ use crate::{
     mm::memory_set,
     tools::logging,
 };
-use core::{
-    arch::naked_asm,
-    hint, slice,
-    sync::atomic::{AtomicBool, Ordering},
-};
+use core::{arch::naked_asm, slice};
 extern crate alloc;
 
 #[macro_use]
@@ -61,6 +59,20 @@ unsafe extern "C" fn _start() -> ! {
     }
 }
 
+/// 其它硬件线程的入口，由启动硬件线程通过 SBI HSM 扩展启动
+#[naked]
+#[no_mangle]
+unsafe extern "C" fn _start_other() -> ! {
+    naked_asm! {"
+        mv tp, a0
+        call {locate_stack}
+        call {main}",
+        locate_stack = sym locate_stack,
+        main = sym others_main,
+        options()
+    }
+}
+
 pub const STACK_SIZE: usize = NUM_HARTS * KERNEL_INIT_STACK_SIZE;
 #[link_section = ".bss.stack"]
 pub static mut KERNEL_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
@@ -106,11 +118,12 @@ pub fn rust_main(hartid: usize, _device_tree_addr: usize) -> ! {
     mm::init();
     task::add_initproc();
     fs::inode::list_apps();
-    // 启动所有硬件线程
-    // sbi::start_all_hart();
     // 中断初始化
     trap::init();
     memory_set::init_kernel_space();
+    drivers::plic::init(hartid);
+    // 内核初始化完成后启动其它硬件线程
+    sbi::start_all_hart();
     #[cfg(test)]
     test_main();
     task::entrap_task()
@@ -121,5 +134,6 @@ fn others_main(hartid: usize) -> ! {
     // 中断初始化
     trap::init();
     memory_set::init_kernel_space();
+    info!("hart {} started", hartid);
     task::entrap_task()
 }
@@ -1,16 +1,23 @@
 use alloc::{collections::BTreeMap, vec::Vec};
 use anyhow::Result;
 use bitflags::bitflags;
-use core::{arch::asm, ops::Range};
+use core::{
+    arch::asm,
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use log::info;
 use riscv::register::satp;
-use spin::{Lazy, Mutex};
+use spin::{Lazy, Mutex, RwLock};
 use xmas_elf::{program::ProgramHeader, ElfFile};
 
 use crate::{
     board::MMIO,
-    config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT},
+    config::{MEMORY_END, PAGE_SIZE, SIGRETURN_TRAMPOLINE, TRAMPOLINE, TRAP_CONTEXT},
+    ipi::tlb_shootdown,
     mm::address::PhysAddr,
+    task::preempt::preempt_point,
+    tools::Table,
 };
 
 use super::{
@@ -66,6 +73,10 @@ pub struct MapArea {
 pub struct MemorySet {
     page_table: PageTable,
     pub areas: Vec<MapArea>,
+    /// 正在使用该地址空间的硬件线程掩码
+    active_harts: AtomicUsize,
+    /// 共享该地址空间的线程占用的 trap 上下文槽位
+    pub trap_slots: Table<()>,
 }
 
 pub static KERNEL_SPACE: Lazy<Mutex<MemorySet>> =
@@ -121,6 +132,11 @@ impl MapArea {
     }
 
     pub fn copy_data(&self, page_table: &mut PageTable, data: &[u8]) {
+        self.copy_data_at(page_table, 0, data)
+    }
+
+    /// 从第一页的 `offset` 处开始复制数据，每复制一页检查一次抢占，调用者不能持有锁
+    pub fn copy_data_at(&self, page_table: &mut PageTable, mut offset: usize, data: &[u8]) {
         assert_eq!(self.map_type, MapType::Framed);
         let mut vpn_iter = self.range.clone();
         let mut start = 0;
@@ -128,12 +144,14 @@ impl MapArea {
             let pte = page_table
                 .translate(vpn_iter.next().unwrap())
                 .expect("Unassigned frame for the virtual address.");
-            let end = (start + PAGE_SIZE).min(data.len());
+            let end = (start + PAGE_SIZE - offset).min(data.len());
             let src = &data[start..end];
             unsafe {
-                pte.ppn().as_bytes()[..src.len()].copy_from_slice(src);
+                pte.ppn().as_bytes()[offset..offset + src.len()].copy_from_slice(src);
             }
             start = end;
+            offset = 0;
+            preempt_point();
         }
     }
 
@@ -190,8 +208,9 @@ impl MapArea {
         }
     }
 
-    pub fn from_ph(ph: ProgramHeader) -> Self {
-        let start_va = ph.virtual_addr() as usize;
+    /// 段被加载到偏移 `base` 处
+    pub fn from_ph(ph: ProgramHeader, base: usize) -> Self {
+        let start_va = base + ph.virtual_addr() as usize;
         let end_va: VirtAddr = (start_va + ph.mem_size() as usize).into();
         let start_va: VirtAddr = start_va.into();
         let mut perm = MapPerm::U;
@@ -209,29 +228,61 @@ impl MapArea {
     }
 }
 
+/// 加载 ELF 文件后用于初始化用户栈的信息
+#[derive(Debug, Clone, Copy)]
+pub struct ElfLoadInfo {
+    /// 程序的入口地址
+    pub entry: usize,
+    /// 开始运行的地址，有动态链接器时为动态链接器的入口
+    pub start: usize,
+    /// 动态链接器的加载地址，没有时为0
+    pub base: usize,
+    pub ustack_base: usize,
+    /// 程序头表在用户空间中的地址
+    pub phdr: usize,
+    pub phent: usize,
+    pub phnum: usize,
+}
+
 impl MemorySet {
     pub fn new_bare() -> Self {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            active_harts: AtomicUsize::new(0),
+            trap_slots: Table::new(),
         }
     }
 
-    pub fn from_existed(space: &MemorySet) -> MemorySet {
+    /// 复制另一个地址空间。每复制一页都重新获取读锁并检查抢占，
+    /// 复制期间被其它线程解除映射的页面不再复制
+    pub fn from_existed(space: &RwLock<MemorySet>) -> MemorySet {
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
+        memory_set.map_sigreturn();
         // copy data sections/trap_context/user_stack
-        for area in space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
+        let areas: Vec<MapArea> = space
+            .read()
+            .areas
+            .iter()
+            .map(MapArea::from_another)
+            .collect();
+        for area in areas {
+            let range = area.range.clone();
+            memory_set.push(area, None);
             // copy data from another space
-            for vpn in area.range.clone() {
-                let src_ppn = space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                unsafe {
-                    dst_ppn.as_bytes().copy_from_slice(src_ppn.as_bytes());
+            for vpn in range {
+                {
+                    let space = space.read();
+                    if let Some(src) = space.translate(vpn).filter(|pte| pte.is_present()) {
+                        let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                        unsafe {
+                            dst_ppn.as_bytes().copy_from_slice(src.ppn().as_bytes());
+                        }
+                    }
                 }
+                preempt_point();
             }
         }
         memory_set
@@ -257,14 +308,66 @@ impl MemorySet {
         }
     }
 
+    /// 用户空间中已映射的大小，包括逻辑段和 `malloc` 分配的页面
+    pub fn mapped_size(&self) -> usize {
+        let pages: usize = self
+            .areas
+            .iter()
+            .map(|area| usize::from(area.range.end) - usize::from(area.range.start))
+            .sum();
+        (pages + self.page_table.leafs.len()) * PAGE_SIZE
+    }
+
+    /// 用户空间占用的物理页面大小。页面在映射时分配且不会换出，即驻留内存的大小
+    pub fn resident_size(&self) -> usize {
+        let pages: usize = self
+            .areas
+            .iter()
+            .filter(|area| area.map_type == MapType::Framed)
+            .map(|area| usize::from(area.range.end) - usize::from(area.range.start))
+            .sum();
+        (pages + self.page_table.leafs.len()) * PAGE_SIZE
+    }
+
     pub fn malloc(&mut self, vpn: VirtPageNum, flags: PTEFlags) -> Result<()> {
         self.page_table.malloc(vpn, flags)
     }
 
-    pub fn free(&mut self, vpn: VirtPageNum) -> Result<()> {
+    pub fn free(&mut self, vpn: VirtPageNum) -> Result<FrameTracker> {
         self.page_table.free(vpn)
     }
 
+    pub fn protect(&mut self, vpn: VirtPageNum, flags: PTEFlags) -> Result<()> {
+        self.page_table.protect(vpn, flags)
+    }
+
+    /// 属于用户的页面：`malloc` 分配的页面或用户逻辑段中的页面。
+    /// 跳板、`sigreturn` 跳板和 trap 上下文由内核映射，不属于用户
+    pub fn is_user_page(&self, vpn: VirtPageNum) -> bool {
+        self.page_table.leafs.contains_key(&vpn)
+            || self.areas.iter().any(|area| {
+                area.map_type == MapType::Framed
+                    && area.perm.contains(MapPerm::U)
+                    && area.range.contains(&vpn)
+            })
+    }
+
+    /// 标记该地址空间在 `hartid` 上是否处于使用中
+    pub fn set_active(&self, hartid: usize, active: bool) {
+        if active {
+            self.active_harts.fetch_or(1 << hartid, Ordering::AcqRel);
+        } else {
+            self.active_harts
+                .fetch_and(!(1 << hartid), Ordering::AcqRel);
+        }
+    }
+
+    /// 修改页表后刷新其它正在使用该地址空间的硬件线程的 TLB，
+    /// 当前硬件线程在返回用户态时刷新
+    pub fn flush_tlb(&self, start: VirtAddr, len: usize) {
+        tlb_shootdown(self.active_harts.load(Ordering::Acquire), start, len);
+    }
+
     pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
         map_area.map_area(&mut self.page_table);
         if let Some(data) = data {
@@ -326,30 +429,69 @@ impl MemorySet {
             .unwrap();
     }
 
-    /// 返回memory_set、入口地址、用户栈地址
-    pub fn from_elf(elf: &ElfFile) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
-        memory_set.map_trampoline();
+    /// 映射用户态可执行的 `sigreturn` 跳板
+    fn map_sigreturn(&mut self) {
+        extern "C" {
+            fn ssigreturn();
+        }
+        self.page_table
+            .map(
+                VirtAddr::from(SIGRETURN_TRAMPOLINE).into(),
+                PhysAddr::from(ssigreturn as usize).into(),
+                PTEFlags::R | PTEFlags::X | PTEFlags::U,
+            )
+            .unwrap();
+    }
+
+    /// 将 ELF 文件的所有 Load 段映射到偏移 `base` 处，返回程序头表地址和映射的最高页号，
+    /// 调用者需要预先校验 ELF 文件
+    fn map_elf(&mut self, elf: &ElfFile, base: usize) -> (usize, VirtPageNum) {
         let header = elf.header;
-        let entry_point = header.pt2.entry_point() as usize;
-        let magic = header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
         let ph_count = header.pt2.ph_count();
+        let ph_offset = header.pt2.ph_offset() as usize;
+        let mut phdr = 0;
         let mut program_vpn_end = VirtPageNum::default();
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
+            let (offset, vaddr) = (ph.offset() as usize, base + ph.virtual_addr() as usize);
+            match ph.get_type().unwrap() {
+                xmas_elf::program::Type::Phdr => phdr = vaddr,
+                // 没有 PT_PHDR 时使用包含程序头表的段计算其地址
+                xmas_elf::program::Type::Load
+                    if phdr == 0
+                        && (offset..offset + ph.file_size() as usize).contains(&ph_offset) =>
+                {
+                    phdr = vaddr + ph_offset - offset
+                }
+                _ => {}
+            }
             if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let map_area = MapArea::from_ph(ph);
-                let offset = ph.offset() as usize;
+                let mut map_area = MapArea::from_ph(ph, base);
                 let data = &elf.input[offset..(offset + ph.file_size() as usize)];
                 let vpn_end = map_area.range.end;
                 if vpn_end > program_vpn_end {
                     program_vpn_end = vpn_end;
                 }
-                memory_set.push(map_area, Some(data));
+                map_area.map_area(&mut self.page_table);
+                map_area.copy_data_at(
+                    &mut self.page_table,
+                    VirtAddr::from(vaddr).page_offset(),
+                    data,
+                );
+                self.areas.push(map_area);
             }
         }
-        assert_ne!(usize::from(program_vpn_end), 0, "empty program");
+        (phdr, program_vpn_end)
+    }
+
+    /// 程序加载到偏移 `base` 处，动态链接器由调用者通过 `map_interp` 加载
+    pub fn from_elf(elf: &ElfFile, base: usize) -> (Self, ElfLoadInfo) {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        memory_set.map_sigreturn();
+        let header = elf.header;
+        let entry_point = base + header.pt2.entry_point() as usize;
+        let (phdr, program_vpn_end) = memory_set.map_elf(elf, base);
         let user_stack_base = VirtAddr::from(program_vpn_end);
         memory_set.push(
             MapArea::new(
@@ -360,7 +502,23 @@ impl MemorySet {
             ),
             None,
         );
-        (memory_set, entry_point, user_stack_base.into())
+        let info = ElfLoadInfo {
+            entry: entry_point,
+            start: entry_point,
+            base: 0,
+            ustack_base: user_stack_base.into(),
+            phdr,
+            phent: header.pt2.ph_entry_size() as usize,
+            phnum: header.pt2.ph_count() as usize,
+        };
+        (memory_set, info)
+    }
+
+    /// 将动态链接器加载到偏移 `base` 处，从动态链接器的入口开始运行
+    pub fn map_interp(&mut self, interp: &ElfFile, base: usize, info: &mut ElfLoadInfo) {
+        self.map_elf(interp, base);
+        info.base = base;
+        info.start = base + interp.header.pt2.entry_point() as usize;
     }
 
     pub fn build_kernel_space() -> Self {
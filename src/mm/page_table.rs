@@ -7,6 +7,10 @@ use crate::config::PAGE_SIZE;
 use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
 use anyhow::{anyhow, Result};
 use bitflags::bitflags;
+use core::{
+    mem::{size_of, MaybeUninit},
+    slice,
+};
 use riscv::register::satp;
 
 bitflags! {
@@ -23,6 +27,9 @@ bitflags! {
     }
 }
 
+/// 软件保留位：`PROT_NONE` 的页面清除 V 位并保留物理页号
+const PTE_PROT_NONE: usize = 1 << 8;
+
 #[derive(Copy, Clone, Default)]
 #[repr(C)]
 pub struct PageTableEntry {
@@ -47,6 +54,10 @@ impl PageTableEntry {
     pub fn is_valid(self) -> bool {
         (self.flags() & PTEFlags::V) != PTEFlags::empty()
     }
+    /// 已映射的页面，包括被设置为不可访问的页面
+    pub fn is_present(self) -> bool {
+        self.is_valid() || self.bits & PTE_PROT_NONE != 0
+    }
     pub fn readable(&self) -> bool {
         (self.flags() & PTEFlags::R) != PTEFlags::empty()
     }
@@ -150,17 +161,40 @@ impl PageTable {
         self.map(vpn, ppn, flags)
     }
 
-    pub fn free(&mut self, vpn: VirtPageNum) -> Result<()> {
-        if self.leafs.remove(&vpn).is_some() {
-            self.unmap_uncheck(vpn)
-        } else {
-            Err(anyhow!("vpn {} is not malloc", vpn))
+    /// 返回被释放的物理页帧，调用者需要在刷新所有硬件线程的 TLB 后再将其释放
+    pub fn free(&mut self, vpn: VirtPageNum) -> Result<FrameTracker> {
+        let frame = self
+            .leafs
+            .remove(&vpn)
+            .ok_or(anyhow!("vpn {} is not malloc", vpn))?;
+        self.unmap_uncheck(vpn)?;
+        Ok(frame)
+    }
+
+    /// 修改已映射页面的权限，没有读写执行权限时清除 V 位。
+    /// 可写的页面总是可读，Sv39 中 W without R 是保留的编码
+    pub fn protect(&mut self, vpn: VirtPageNum, mut flags: PTEFlags) -> Result<()> {
+        match self.find_pte(vpn) {
+            Some(pte) if pte.is_present() => {
+                if flags.contains(PTEFlags::W) {
+                    flags |= PTEFlags::R;
+                }
+                *pte = if flags.intersects(PTEFlags::R | PTEFlags::X) {
+                    PageTableEntry::new(pte.ppn(), flags | PTEFlags::V)
+                } else {
+                    let mut entry = PageTableEntry::new(pte.ppn(), flags - PTEFlags::V);
+                    entry.bits |= PTE_PROT_NONE;
+                    entry
+                };
+                Ok(())
+            }
+            _ => Err(anyhow!("vpn {} is not mapped", vpn)),
         }
     }
 
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> Result<()> {
         let pte_entry = self.find_pte_entry(vpn);
-        if !pte_entry.is_valid() {
+        if !pte_entry.is_present() {
             *pte_entry = PageTableEntry::new(ppn, flags | PTEFlags::V);
             Ok(())
         } else {
@@ -176,7 +210,7 @@ impl PageTable {
     pub fn unmap_uncheck(&mut self, vpn: VirtPageNum) -> Result<()> {
         let pte = self.find_pte(vpn).unwrap();
         // assert_ne!(pte.flags() & PTEFlags::U, PTEFlags::empty());
-        if pte.is_valid() {
+        if pte.is_present() {
             *pte = PageTableEntry::empty();
             Ok(())
         } else {
@@ -230,6 +264,72 @@ pub unsafe fn translated_string(space: &MemorySet, ptr: VirtAddr, len: usize) ->
     String::from_utf8(buffer).map_err(|err| anyhow!("{}", err))
 }
 
+/// 字符串的最大长度，包括结尾的 `\0`
+pub const MAX_STR_LEN: usize = 4096;
+/// 字符串数组的最大元素个数
+pub const MAX_ARG_NUM: usize = 256;
+
+/// 读取以 `\0` 结尾的用户字符串
+pub unsafe fn translated_str(space: &MemorySet, ptr: VirtAddr) -> Result<String> {
+    let mut buffer = Vec::new();
+    let mut va = ptr;
+    loop {
+        let ppn = space
+            .translate(va.floor())
+            .ok_or(anyhow!("illegal address {}", va))?
+            .ppn();
+        let bytes = &ppn.as_bytes()[va.page_offset()..];
+        if let Some(len) = bytes.iter().position(|&byte| byte == 0) {
+            buffer.extend_from_slice(&bytes[..len]);
+            break;
+        }
+        buffer.extend_from_slice(bytes);
+        if buffer.len() >= MAX_STR_LEN {
+            return Err(anyhow!("string too long at {}", ptr));
+        }
+        va = VirtAddr::from(va.floor().offset(1));
+    }
+    String::from_utf8(buffer).map_err(|err| anyhow!("{}", err))
+}
+
+/// 读取以空指针结尾的用户字符串指针数组，空指针表示空数组
+pub unsafe fn translated_str_array(space: &MemorySet, ptr: *const usize) -> Result<Vec<String>> {
+    let mut result = Vec::new();
+    if ptr.is_null() {
+        return Ok(result);
+    }
+    loop {
+        let str_ptr = *translated_refmut(space, ptr.add(result.len()) as *mut usize)?;
+        if str_ptr == 0 {
+            break;
+        }
+        if result.len() >= MAX_ARG_NUM {
+            return Err(anyhow!("too many strings at {:#x}", ptr as usize));
+        }
+        result.push(translated_str(space, str_ptr.into())?);
+    }
+    Ok(result)
+}
+
+/// 将 `value` 复制到用户地址 `ptr`，允许跨页
+pub unsafe fn copy_to_user<T: Copy>(space: &MemorySet, ptr: VirtAddr, value: &T) -> Result<()> {
+    let bytes = slice::from_raw_parts(value as *const T as *const u8, size_of::<T>());
+    BufferHandle::new(translated_byte_buffer(space, ptr, bytes.len())?).write(bytes);
+    Ok(())
+}
+
+/// 从用户地址 `ptr` 读取 `T`，允许跨页，`T` 的任意字节序列都必须是合法的值
+pub unsafe fn copy_from_user<T: Copy>(space: &MemorySet, ptr: VirtAddr) -> Result<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>());
+    let mut offset = 0;
+    for part in translated_byte_buffer(space, ptr, bytes.len())? {
+        bytes[offset..offset + part.len()].copy_from_slice(part);
+        offset += part.len();
+    }
+    Ok(value.assume_init())
+}
+
 pub unsafe fn translated_refmut<T: 'static>(space: &MemorySet, ptr: *mut T) -> Result<&mut T> {
     //println!("into translated_refmut!");
     let va = ptr as usize;
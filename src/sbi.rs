@@ -1,9 +1,8 @@
 #![allow(unused)] // 此行在文件最开头
 use core::arch::asm;
-use log::{error, info};
-use sbi_rt::{self, SbiRet};
+use log::error;
 
-use crate::{_start, config::NUM_HARTS, println, rust_main};
+use crate::{_start_other, config::NUM_HARTS};
 const SBI_SET_TIMER: usize = 0;
 const SBI_CONSOLE_PUTCHAR: usize = 1;
 const SBI_CONSOLE_GETCHAR: usize = 2;
@@ -49,7 +48,10 @@ pub fn shutdown() -> ! {
 pub fn start_all_hart() {
     for id in 0..NUM_HARTS {
         if get_hartid() != id {
-            sbi_rt::hart_start(id, _start as usize, 0);
+            let ret = sbi_rt::hart_start(id, _start_other as usize, 0);
+            if ret.error != 0 {
+                error!("failed to start hart {}: {:?}", id, ret);
+            }
         }
     }
 }
@@ -0,0 +1,167 @@
+use alloc::vec::Vec;
+
+use anyhow::Result;
+
+use crate::{
+    mm::page_table::{copy_from_user, copy_to_user},
+    syscall_unwarp,
+    task::{
+        cred::{Credentials, Ids, NGROUPS_MAX},
+        processor::Schedule,
+    },
+};
+
+use super::{
+    errno::{EFAULT, EINVAL},
+    EXEC_SUCCEE,
+};
+
+pub(super) trait SysCred {
+    fn sys_getuid(&self) -> isize;
+    fn sys_geteuid(&self) -> isize;
+    fn sys_getgid(&self) -> isize;
+    fn sys_getegid(&self) -> isize;
+    fn sys_setuid(&self, uid: u32) -> isize;
+    fn sys_setgid(&self, gid: u32) -> isize;
+    fn sys_setreuid(&self, ruid: u32, euid: u32) -> isize;
+    fn sys_setregid(&self, rgid: u32, egid: u32) -> isize;
+    fn sys_setresuid(&self, ruid: u32, euid: u32, suid: u32) -> isize;
+    fn sys_setresgid(&self, rgid: u32, egid: u32, sgid: u32) -> isize;
+    fn sys_getresuid(&self, ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> isize;
+    fn sys_getresgid(&self, rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> isize;
+    fn sys_getgroups(&self, size: usize, list: *mut u32) -> isize;
+    fn sys_setgroups(&self, size: usize, list: *const u32) -> isize;
+}
+
+/// -1 表示不修改
+fn optional_id(id: u32) -> Option<u32> {
+    (id != u32::MAX).then_some(id)
+}
+
+/// 在当前进程的身份上执行修改
+fn update_cred<T: Schedule>(
+    sched: &T,
+    f: impl FnOnce(&mut Credentials) -> Result<()>,
+) -> Result<()> {
+    f(&mut sched.current_task().process.inner.write().cred)
+}
+
+/// 写回实际、有效和保存的 id
+fn write_ids<T: Schedule>(sched: &T, ids: Ids, ptrs: [*mut u32; 3]) -> isize {
+    let task = sched.current_task();
+    let space = unsafe { task.space() };
+    for (ptr, id) in ptrs.into_iter().zip([ids.real, ids.effective, ids.saved]) {
+        syscall_unwarp!(unsafe { copy_to_user(space, (ptr as usize).into(), &id) }
+            .map_err(|err| EFAULT.with(err)));
+    }
+    EXEC_SUCCEE
+}
+
+impl<T: Schedule> SysCred for T {
+    fn sys_getuid(&self) -> isize {
+        self.current_task().process.inner.read().cred.user.real as isize
+    }
+
+    fn sys_geteuid(&self) -> isize {
+        self.current_task().process.inner.read().cred.user.effective as isize
+    }
+
+    fn sys_getgid(&self) -> isize {
+        self.current_task().process.inner.read().cred.group.real as isize
+    }
+
+    fn sys_getegid(&self) -> isize {
+        self.current_task()
+            .process
+            .inner
+            .read()
+            .cred
+            .group
+            .effective as isize
+    }
+
+    fn sys_setuid(&self, uid: u32) -> isize {
+        syscall_unwarp!(update_cred(self, |cred| cred.setuid(uid)));
+        EXEC_SUCCEE
+    }
+
+    fn sys_setgid(&self, gid: u32) -> isize {
+        syscall_unwarp!(update_cred(self, |cred| cred.setgid(gid)));
+        EXEC_SUCCEE
+    }
+
+    fn sys_setreuid(&self, ruid: u32, euid: u32) -> isize {
+        syscall_unwarp!(update_cred(self, |cred| {
+            cred.setreuid(optional_id(ruid), optional_id(euid))
+        }));
+        EXEC_SUCCEE
+    }
+
+    fn sys_setregid(&self, rgid: u32, egid: u32) -> isize {
+        syscall_unwarp!(update_cred(self, |cred| {
+            cred.setregid(optional_id(rgid), optional_id(egid))
+        }));
+        EXEC_SUCCEE
+    }
+
+    fn sys_setresuid(&self, ruid: u32, euid: u32, suid: u32) -> isize {
+        syscall_unwarp!(update_cred(self, |cred| {
+            cred.setresuid(optional_id(ruid), optional_id(euid), optional_id(suid))
+        }));
+        EXEC_SUCCEE
+    }
+
+    fn sys_setresgid(&self, rgid: u32, egid: u32, sgid: u32) -> isize {
+        syscall_unwarp!(update_cred(self, |cred| {
+            cred.setresgid(optional_id(rgid), optional_id(egid), optional_id(sgid))
+        }));
+        EXEC_SUCCEE
+    }
+
+    fn sys_getresuid(&self, ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> isize {
+        let ids = self.current_task().process.inner.read().cred.user;
+        write_ids(self, ids, [ruid, euid, suid])
+    }
+
+    fn sys_getresgid(&self, rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> isize {
+        let ids = self.current_task().process.inner.read().cred.group;
+        write_ids(self, ids, [rgid, egid, sgid])
+    }
+
+    /// `size` 为0时只返回附加组的数量
+    fn sys_getgroups(&self, size: usize, list: *mut u32) -> isize {
+        let task = self.current_task();
+        let groups = task.process.inner.read().cred.groups.clone();
+        if size == 0 {
+            return groups.len() as isize;
+        }
+        if size < groups.len() {
+            return -EINVAL.0;
+        }
+        let space = unsafe { task.space() };
+        for (i, gid) in groups.iter().enumerate() {
+            syscall_unwarp!(unsafe {
+                copy_to_user(space, (list.wrapping_add(i) as usize).into(), gid)
+            }
+            .map_err(|err| EFAULT.with(err)));
+        }
+        groups.len() as isize
+    }
+
+    fn sys_setgroups(&self, size: usize, list: *const u32) -> isize {
+        if size > NGROUPS_MAX {
+            return -EINVAL.0;
+        }
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let mut groups = Vec::with_capacity(size);
+        for i in 0..size {
+            groups.push(syscall_unwarp!(unsafe {
+                copy_from_user::<u32>(space, (list.wrapping_add(i) as usize).into())
+            }
+            .map_err(|err| EFAULT.with(err))));
+        }
+        syscall_unwarp!(update_cred(self, |cred| cred.setgroups(groups)));
+        EXEC_SUCCEE
+    }
+}
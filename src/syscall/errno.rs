@@ -0,0 +1,70 @@
+use core::fmt::{self, Display};
+
+use anyhow::Error;
+
+/// 与 Linux 一致的错误码，系统调用失败时返回其相反数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub isize);
+
+pub const EPERM: Errno = Errno(1);
+pub const ENOENT: Errno = Errno(2);
+pub const ESRCH: Errno = Errno(3);
+pub const EINTR: Errno = Errno(4);
+pub const EIO: Errno = Errno(5);
+pub const E2BIG: Errno = Errno(7);
+pub const ENOEXEC: Errno = Errno(8);
+pub const ECHILD: Errno = Errno(10);
+pub const EAGAIN: Errno = Errno(11);
+pub const ENOMEM: Errno = Errno(12);
+pub const EACCES: Errno = Errno(13);
+pub const EFAULT: Errno = Errno(14);
+pub const EBUSY: Errno = Errno(16);
+pub const EINVAL: Errno = Errno(22);
+pub const EMFILE: Errno = Errno(24);
+pub const ENOTTY: Errno = Errno(25);
+pub const ENOSPC: Errno = Errno(28);
+pub const ELOOP: Errno = Errno(40);
+pub const ELIBBAD: Errno = Errno(80);
+/// 只在内核中使用：被信号中断的系统调用，由信号处理决定重新执行或返回 `EINTR`
+pub const ERESTARTSYS: Errno = Errno(512);
+
+impl Errno {
+    /// 附带错误信息，可以通过 `errno_of` 取回错误码
+    pub fn with(self, msg: impl Display + fmt::Debug + Send + Sync + 'static) -> Error {
+        Error::msg(self).context(msg)
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            EPERM => "EPERM",
+            ENOENT => "ENOENT",
+            ESRCH => "ESRCH",
+            EINTR => "EINTR",
+            EIO => "EIO",
+            E2BIG => "E2BIG",
+            ENOEXEC => "ENOEXEC",
+            ECHILD => "ECHILD",
+            EAGAIN => "EAGAIN",
+            ENOMEM => "ENOMEM",
+            EACCES => "EACCES",
+            EFAULT => "EFAULT",
+            EINVAL => "EINVAL",
+            EMFILE => "EMFILE",
+            ENOTTY => "ENOTTY",
+            ENOSPC => "ENOSPC",
+            ELOOP => "ELOOP",
+            ELIBBAD => "ELIBBAD",
+            ERESTARTSYS => "ERESTARTSYS",
+            _ => return write!(f, "errno {}", self.0),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 错误对应的系统调用返回值，没有错误码的错误返回 -1
+pub fn errno_of(err: &Error) -> isize {
+    err.downcast_ref::<Errno>()
+        .map_or(super::EXEC_FAIL, |errno| -errno.0)
+}
@@ -1,17 +1,35 @@
 use crate::{
     fs::{
-        inode::{open_file, OpenFlags},
+        inode::{file_metadata, open_file, set_file_metadata, OpenFlags, S_ISGID, S_ISUID},
         pipe::make_pipe,
+        stdio::TTY,
     },
     mm::{
         address::VirtAddr,
-        page_table::{translated_byte_buffer, translated_refmut, translated_string, BufferHandle},
+        page_table::{
+            copy_from_user, copy_to_user, translated_byte_buffer, translated_refmut,
+            translated_string, BufferHandle,
+        },
     },
     syscall_unwarp,
-    task::processor::Schedule,
+    task::{
+        process::process_group, processor::Schedule, rlimit::RLIMIT_NOFILE, signal::SignalFlags,
+    },
+};
+
+use super::{
+    errno::{EFAULT, EINVAL, ENOENT, ENOTTY, EPERM, ERESTARTSYS},
+    EXEC_FAIL, EXEC_SUCCEE,
 };
 
-use super::{EXEC_FAIL, EXEC_SUCCEE};
+/// 成为调用者所在会话的控制终端
+const TIOCSCTTY: usize = 0x540e;
+/// 获取前台进程组
+const TIOCGPGRP: usize = 0x540f;
+/// 设置前台进程组
+const TIOCSPGRP: usize = 0x5410;
+/// 获取终端所属的会话
+const TIOCGSID: usize = 0x5429;
 
 pub(super) trait SysFs {
     fn sys_write(&self, fd: usize, buf: usize, len: usize) -> isize;
@@ -20,14 +38,16 @@ pub(super) trait SysFs {
     fn sys_close(&self, fd: usize) -> isize;
     fn sys_pipe(&self, pipe: *mut usize) -> isize;
     fn sys_dup(&self, fd: usize) -> isize;
+    fn sys_ioctl(&self, fd: usize, cmd: usize, arg: usize) -> isize;
+    fn sys_chmod(&self, ptr: VirtAddr, len: usize, mode: u32) -> isize;
+    fn sys_chown(&self, ptr: VirtAddr, len: usize, uid: u32, gid: u32) -> isize;
 }
 
 impl<T: Schedule> SysFs for T {
     fn sys_write(&self, fd: usize, buf: usize, len: usize) -> isize {
         let task = self.current_task();
-        let local = task.process.inner.read();
-        let fd_table = &local.fd_table;
-        if let Some(file) = fd_table.get(fd) {
+        let file = task.process.inner.read().fd_table.read().get(fd).cloned();
+        if let Some(file) = file {
             if file.writable() {
                 let buffer = unsafe {
                     BufferHandle::new(syscall_unwarp!(translated_byte_buffer(
@@ -44,10 +64,13 @@ impl<T: Schedule> SysFs for T {
 
     fn sys_read(&self, fd: usize, buf: usize, len: usize) -> isize {
         let task = self.current_task();
-        let local = task.process.inner.read();
-        let fd_table = &local.fd_table;
-        if let Some(file) = fd_table.get(fd) {
+        let file = task.process.inner.read().fd_table.read().get(fd).cloned();
+        if let Some(file) = file {
             if file.readable() {
+                // 后台进程组读取控制终端时被 `SIGTTIN` 停止
+                if file.is_tty() {
+                    syscall_unwarp!(TTY.job_control(&task, SignalFlags::SIGTTIN));
+                }
                 let buffer = unsafe {
                     BufferHandle::new(syscall_unwarp!(translated_byte_buffer(
                         task.space(),
@@ -55,7 +78,12 @@ impl<T: Schedule> SysFs for T {
                         len
                     )))
                 };
-                return file.read(buffer) as isize;
+                let read = file.read(buffer);
+                // 读取终端时被信号打断，没有读到任何数据
+                if read == 0 && file.is_tty() && task.shared.has_signal(&task.process.shared) {
+                    return -ERESTARTSYS.0;
+                }
+                return read as isize;
             }
         }
         EXEC_FAIL
@@ -65,17 +93,20 @@ impl<T: Schedule> SysFs for T {
         let task = self.current_task();
         let path = unsafe { syscall_unwarp!(translated_string(task.space(), ptr, len)) };
         let flags = OpenFlags::from_bits_truncate(flags as u8);
-        if let Some(inode) = open_file(&path, flags) {
-            task.process.inner.write().fd_table.push(inode) as isize
-        } else {
-            EXEC_FAIL
+        let inode = syscall_unwarp!(open_file(&path, flags, &task.process.inner.read().cred));
+        let local = task.process.inner.read();
+        let mut fd_table = local.fd_table.write();
+        let fd = syscall_unwarp!(fd_table.alloc(inode, local.rlimits.cur(RLIMIT_NOFILE)));
+        if flags.contains(OpenFlags::CLOEXEC) {
+            fd_table.cloexec.insert(fd);
         }
+        fd as isize
     }
 
     fn sys_close(&self, fd: usize) -> isize {
         let task = self.current_task();
-        let mut local = task.process.inner.write();
-        if local.fd_table.remove(fd).is_some() {
+        let local = task.process.inner.read();
+        if local.fd_table.write().remove(fd).is_some() {
             EXEC_SUCCEE
         } else {
             EXEC_FAIL
@@ -86,10 +117,11 @@ impl<T: Schedule> SysFs for T {
         let task = self.current_task();
         let space = unsafe { task.space() };
         let (pipe_read, pipe_write) = make_pipe();
-        let mut local = task.process.inner.write();
-        let fd_table = &mut local.fd_table;
-        let read_fd = fd_table.push(pipe_read);
-        let write_fd = fd_table.push(pipe_write);
+        let local = task.process.inner.read();
+        let read_fd = syscall_unwarp!(local.alloc_fd(pipe_read));
+        let write_fd = syscall_unwarp!(local.alloc_fd(pipe_write).inspect_err(|_| {
+            local.fd_table.write().remove(read_fd);
+        }));
         unsafe {
             let read_fd_ptr = syscall_unwarp!(translated_refmut(space, pipe));
             *read_fd_ptr = read_fd;
@@ -100,13 +132,111 @@ impl<T: Schedule> SysFs for T {
     }
     fn sys_dup(&self, fd: usize) -> isize {
         let task = self.current_task();
-        let mut local = task.process.inner.write();
-        let fd_table = &mut local.fd_table;
-        if let Some(file) = fd_table.get(fd) {
-            let cp_file = file.clone();
-            fd_table.push(cp_file) as isize
+        let local = task.process.inner.read();
+        let file = local.fd_table.read().get(fd).cloned();
+        if let Some(cp_file) = file {
+            syscall_unwarp!(local.alloc_fd(cp_file)) as isize
         } else {
             EXEC_FAIL
         }
     }
+
+    /// 目前只支持控制台的作业控制命令
+    fn sys_ioctl(&self, fd: usize, cmd: usize, arg: usize) -> isize {
+        let task = self.current_task();
+        let Some(file) = task.process.inner.read().fd_table.read().get(fd).cloned() else {
+            return EXEC_FAIL;
+        };
+        if !file.is_tty() {
+            return -ENOTTY.0;
+        }
+        let process = &task.process;
+        let space = unsafe { task.space() };
+        match cmd {
+            TIOCSCTTY => {
+                // 只有会话首进程可以获得不属于其它会话的控制终端
+                let session = TTY.session();
+                if !process.is_session_leader() || session != 0 && session != process.get_sid() {
+                    return -EPERM.0;
+                }
+                TTY.attach(process.get_sid(), process.get_pgid());
+            }
+            _ if TTY.session() != process.get_sid() => return -ENOTTY.0,
+            TIOCGPGRP => syscall_unwarp!(unsafe {
+                copy_to_user(space, arg.into(), &(TTY.foreground() as i32))
+            }
+            .map_err(|err| EFAULT.with(err))),
+            TIOCGSID => {
+                syscall_unwarp!(
+                    unsafe { copy_to_user(space, arg.into(), &(TTY.session() as i32)) }
+                        .map_err(|err| EFAULT.with(err))
+                )
+            }
+            TIOCSPGRP => {
+                // 后台进程组修改前台进程组时被 `SIGTTOU` 停止
+                syscall_unwarp!(TTY.job_control(&task, SignalFlags::SIGTTOU));
+                let pgid = syscall_unwarp!(unsafe { copy_from_user::<i32>(space, arg.into()) }
+                    .map_err(|err| EFAULT.with(err))) as isize;
+                if pgid < 0 {
+                    return -EINVAL.0;
+                }
+                if !process_group(pgid)
+                    .iter()
+                    .any(|member| member.get_sid() == process.get_sid())
+                {
+                    return -EPERM.0;
+                }
+                TTY.set_foreground(pgid);
+            }
+            _ => return -ENOTTY.0,
+        }
+        EXEC_SUCCEE
+    }
+
+    /// 只有文件所有者和 root 可以修改权限，不属于文件所在组的用户设置的 setgid 位被清除
+    fn sys_chmod(&self, ptr: VirtAddr, len: usize, mode: u32) -> isize {
+        let task = self.current_task();
+        let path = unsafe { syscall_unwarp!(translated_string(task.space(), ptr, len)) };
+        let Some(mut metadata) = file_metadata(&path) else {
+            return -ENOENT.0;
+        };
+        let inner = task.process.inner.read();
+        if !inner.cred.is_owner(&metadata) {
+            return -EPERM.0;
+        }
+        metadata.mode = mode & 0o7777;
+        if !inner.cred.is_root() && !inner.cred.in_group(metadata.gid) {
+            metadata.mode &= !S_ISGID;
+        }
+        set_file_metadata(&path, metadata);
+        EXEC_SUCCEE
+    }
+
+    /// `uid` 或 `gid` 为 -1 时不修改。只有 root 可以修改所有者，
+    /// 所有者只能把文件交给自己所在的组，非 root 修改后清除 setuid 和 setgid 位
+    fn sys_chown(&self, ptr: VirtAddr, len: usize, uid: u32, gid: u32) -> isize {
+        let task = self.current_task();
+        let path = unsafe { syscall_unwarp!(translated_string(task.space(), ptr, len)) };
+        let Some(mut metadata) = file_metadata(&path) else {
+            return -ENOENT.0;
+        };
+        let inner = task.process.inner.read();
+        let cred = &inner.cred;
+        let uid = if uid == u32::MAX { metadata.uid } else { uid };
+        let gid = if gid == u32::MAX { metadata.gid } else { gid };
+        if !cred.is_root()
+            && (uid != metadata.uid
+                || !cred.is_owner(&metadata)
+                || gid != metadata.gid && !cred.in_group(gid))
+        {
+            return -EPERM.0;
+        }
+        if !cred.is_root() {
+            metadata.mode &= !(S_ISUID | S_ISGID);
+        }
+        metadata.uid = uid;
+        metadata.gid = gid;
+        set_file_metadata(&path, metadata);
+        EXEC_SUCCEE
+    }
 }
@@ -1,15 +1,20 @@
+use alloc::vec::Vec;
+use log::warn;
+
 use crate::{
+    config::PAGE_SIZE,
     mm::{address::VirtAddr, memory_set::MapPerm, page_table::PTEFlags},
     syscall::EXEC_FAIL,
     syscall_unwarp,
-    task::processor::Schedule,
+    task::{processor::Schedule, rlimit::RLIMIT_AS},
 };
 
-use super::EXEC_SUCCEE;
+use super::{errno::ENOMEM, EXEC_SUCCEE};
 
 pub(super) trait SysMm {
     fn sys_munmap(&self, va: VirtAddr, len: usize) -> isize;
     fn sys_mmap(&self, va: VirtAddr, len: usize, perm: usize, fd: usize) -> isize;
+    fn sys_mprotect(&self, va: VirtAddr, len: usize, perm: usize) -> isize;
 }
 
 impl<T: Schedule> SysMm for T {
@@ -17,10 +22,22 @@ impl<T: Schedule> SysMm for T {
         let range = va.floor()..va.offset(len as isize).ceil();
         let task = self.current_task();
         let user_space = unsafe { task.space() };
+        let mut frames = Vec::new();
+        let mut result = EXEC_SUCCEE;
         for vpn in range {
-            syscall_unwarp!(user_space.free(vpn));
+            match user_space.free(vpn) {
+                Ok(frame) => frames.push(frame),
+                Err(err) => {
+                    warn!("{}", err);
+                    result = EXEC_FAIL;
+                    break;
+                }
+            }
         }
-        EXEC_SUCCEE
+        // 其它硬件线程的 TLB 刷新完成前不能释放物理页帧
+        user_space.flush_tlb(va, len);
+        drop(frames);
+        result
     }
 
     fn sys_mmap(&self, va: VirtAddr, len: usize, perm: usize, _fd: usize) -> isize {
@@ -30,9 +47,47 @@ impl<T: Schedule> SysMm for T {
         let range = va.floor()..va.offset(len as isize).ceil();
         let task = self.current_task();
         let user_space = unsafe { task.space() };
+        // 映射后的地址空间不能超过 `RLIMIT_AS`
+        let pages = usize::from(range.end) - usize::from(range.start);
+        let limit = task.process.inner.read().rlimits.cur(RLIMIT_AS);
+        if user_space.mapped_size().saturating_add(pages * PAGE_SIZE) > limit {
+            return -ENOMEM.0;
+        }
         for vpn in range {
             syscall_unwarp!(user_space.malloc(vpn, flags));
         }
+        task.process
+            .shared
+            .usage
+            .update_rss(user_space.resident_size());
         EXEC_SUCCEE
     }
+
+    fn sys_mprotect(&self, va: VirtAddr, len: usize, perm: usize) -> isize {
+        let perm = MapPerm::from_bits_truncate(perm as u8) & MapPerm::RWX;
+        let range = va.floor()..va.offset(len as isize).ceil();
+        let task = self.current_task();
+        let user_space = unsafe { task.space() };
+        // 先检查整个范围，只允许修改用户自己的页面，修改过的页表项总是在返回前刷新
+        for vpn in range.clone() {
+            let user = user_space
+                .translate(vpn)
+                .is_some_and(|pte| pte.is_present() && pte.flags().contains(PTEFlags::U))
+                && user_space.is_user_page(vpn);
+            if !user {
+                return -ENOMEM.0;
+            }
+        }
+        let perm = PTEFlags::from_bits_truncate(perm.bits()) | PTEFlags::U;
+        let mut result = EXEC_SUCCEE;
+        for vpn in range {
+            if let Err(err) = user_space.protect(vpn, perm) {
+                warn!("{}", err);
+                result = EXEC_FAIL;
+                break;
+            }
+        }
+        user_space.flush_tlb(va, len);
+        result
+    }
 }
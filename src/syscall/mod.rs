@@ -1,32 +1,89 @@
+mod cred;
+pub mod errno;
 mod fs;
 mod mm;
 mod process;
+mod sched;
 mod sync;
 use log::warn;
 
-use self::{fs::*, mm::*, process::*, sync::*};
+use self::{cred::*, fs::*, mm::*, process::*, sched::*, sync::*};
 use crate::task::processor::Schedule;
 
 const SYSCALL_DUP: usize = 24;
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_CHMOD: usize = 53;
+const SYSCALL_CHOWN: usize = 54;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_SET_TID_ADDRESS: usize = 96;
 const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_GETITIMER: usize = 102;
+const SYSCALL_SETITIMER: usize = 103;
+const SYSCALL_TIMER_CREATE: usize = 107;
+const SYSCALL_TIMER_GETTIME: usize = 108;
+const SYSCALL_TIMER_GETOVERRUN: usize = 109;
+const SYSCALL_TIMER_SETTIME: usize = 110;
+const SYSCALL_TIMER_DELETE: usize = 111;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
+const SYSCALL_SCHED_SETPARAM: usize = 118;
+const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+const SYSCALL_SCHED_GETPARAM: usize = 121;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_SCHED_GET_PRIORITY_MAX: usize = 125;
+const SYSCALL_SCHED_GET_PRIORITY_MIN: usize = 126;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGALTSTACK: usize = 132;
 const SYSCALL_SIGACTION: usize = 134;
 const SYSCALL_SIGPROCMASK: usize = 135;
-const SYSCALL_SIGRETURN: usize = 139;
+pub const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SETPRIORITY: usize = 140;
+const SYSCALL_GETPRIORITY: usize = 141;
+const SYSCALL_SETREGID: usize = 143;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETREUID: usize = 145;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_SETRESUID: usize = 147;
+const SYSCALL_GETRESUID: usize = 148;
+const SYSCALL_SETRESGID: usize = 149;
+const SYSCALL_GETRESGID: usize = 150;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETSID: usize = 156;
+const SYSCALL_SETSID: usize = 157;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
+const SYSCALL_GETRUSAGE: usize = 165;
+const SYSCALL_GETGROUPS: usize = 158;
+const SYSCALL_SETGROUPS: usize = 159;
 const SYSCALL_TIME: usize = 169;
 const SYSCALL_GET_PID: usize = 172;
+const SYSCALL_GETTID: usize = 178;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_GETEUID: usize = 175;
+const SYSCALL_GETGID: usize = 176;
+const SYSCALL_GETEGID: usize = 177;
 const SYSCALL_MUNMAP: usize = 215;
-const SYSCALL_FORK: usize = 220;
+const SYSCALL_CLONE: usize = 220;
 const SYSCALL_EXECVE: usize = 221;
 const SYSCALL_MMAP: usize = 222;
-const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_WAIT4: usize = 260;
+const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_SCHED_SETATTR: usize = 274;
+const SYSCALL_SCHED_GETATTR: usize = 275;
+const SYSCALL_SPAWN: usize = 400;
+/// Linux 的 `nanosleep` 编号已被 `sleep` 占用
+const SYSCALL_NANOSLEEP: usize = 401;
 
 const EXEC_SUCCEE: isize = 0;
 const EXEC_FAIL: isize = -1;
@@ -40,21 +97,139 @@ impl<T: Schedule> Syscall for T {
     fn syscall(&self, syscall_id: usize, args: [usize; 6]) -> isize {
         match syscall_id {
             SYSCALL_DUP => self.sys_dup(args[0]),
+            SYSCALL_IOCTL => self.sys_ioctl(args[0], args[1], args[2]),
+            SYSCALL_CHMOD => self.sys_chmod(args[0].into(), args[1], args[2] as u32),
+            SYSCALL_CHOWN => {
+                self.sys_chown(args[0].into(), args[1], args[2] as u32, args[3] as u32)
+            }
             SYSCALL_OPEN => self.sys_open(args[0].into(), args[1], args[2] as u32),
             SYSCALL_CLOSE => self.sys_close(args[0]),
             SYSCALL_PIPE => self.sys_pipe(args[0] as *mut usize),
             SYSCALL_READ => self.sys_read(args[0], args[1], args[2]),
             SYSCALL_WRITE => self.sys_write(args[0], args[1], args[2]),
             SYSCALL_EXIT => self.sys_exit(args[0] as i32),
+            SYSCALL_SET_TID_ADDRESS => self.sys_set_tid_address(args[0] as *mut _),
             SYSCALL_YIELD => self.sys_yield(),
+            SYSCALL_SCHED_SETPARAM => {
+                self.sys_sched_setparam(args[0] as isize, args[1] as *const _)
+            }
+            SYSCALL_SCHED_SETSCHEDULER => {
+                self.sys_sched_setscheduler(args[0] as isize, args[1] as u32, args[2] as *const _)
+            }
+            SYSCALL_SCHED_GETSCHEDULER => self.sys_sched_getscheduler(args[0] as isize),
+            SYSCALL_SCHED_GETPARAM => self.sys_sched_getparam(args[0] as isize, args[1] as *mut _),
+            SYSCALL_SCHED_SETAFFINITY => {
+                self.sys_sched_setaffinity(args[0] as isize, args[1], args[2] as *const _)
+            }
+            SYSCALL_SCHED_GETAFFINITY => {
+                self.sys_sched_getaffinity(args[0] as isize, args[1], args[2] as *mut _)
+            }
+            SYSCALL_SCHED_GET_PRIORITY_MAX => self.sys_sched_get_priority_max(args[0] as u32),
+            SYSCALL_SCHED_GET_PRIORITY_MIN => self.sys_sched_get_priority_min(args[0] as u32),
+            SYSCALL_SCHED_SETATTR => {
+                self.sys_sched_setattr(args[0] as isize, args[1] as *const _, args[2] as u32)
+            }
+            SYSCALL_SCHED_GETATTR => self.sys_sched_getattr(
+                args[0] as isize,
+                args[1] as *mut _,
+                args[2] as u32,
+                args[3] as u32,
+            ),
             SYSCALL_TIME => sys_get_time(),
             SYSCALL_GET_PID => self.sys_get_pid(),
+            SYSCALL_GETTID => self.sys_gettid(),
+            SYSCALL_KILL => self.sys_kill(args[0] as isize, args[1]),
+            SYSCALL_SIGACTION => {
+                self.sys_sigaction(args[0], args[1] as *const _, args[2] as *mut _, args[3])
+            }
+            SYSCALL_SIGPROCMASK => {
+                self.sys_sigprocmask(args[0], args[1] as *const _, args[2] as *mut _, args[3])
+            }
+            SYSCALL_SIGRETURN => self.sys_sigreturn(),
+            SYSCALL_SIGALTSTACK => self.sys_sigaltstack(args[0] as *const _, args[1] as *mut _),
+            SYSCALL_SETPRIORITY => {
+                self.sys_set_priority(args[0], args[1] as isize, args[2] as isize)
+            }
+            SYSCALL_GETPRIORITY => self.sys_get_priority(args[0], args[1] as isize),
+            SYSCALL_SETPGID => self.sys_setpgid(args[0] as isize, args[1] as isize),
+            SYSCALL_GETPGID => self.sys_getpgid(args[0] as isize),
+            SYSCALL_GETSID => self.sys_getsid(args[0] as isize),
+            SYSCALL_SETSID => self.sys_setsid(),
+            SYSCALL_GETRLIMIT => self.sys_getrlimit(args[0], args[1] as *mut _),
+            SYSCALL_SETRLIMIT => self.sys_setrlimit(args[0], args[1] as *const _),
+            SYSCALL_GETRUSAGE => self.sys_getrusage(args[0] as isize, args[1] as *mut _),
+            SYSCALL_TIMES => self.sys_times(args[0] as *mut _),
+            SYSCALL_PRLIMIT64 => self.sys_prlimit(
+                args[0] as isize,
+                args[1],
+                args[2] as *const _,
+                args[3] as *mut _,
+            ),
+            SYSCALL_GETUID => self.sys_getuid(),
+            SYSCALL_GETEUID => self.sys_geteuid(),
+            SYSCALL_GETGID => self.sys_getgid(),
+            SYSCALL_GETEGID => self.sys_getegid(),
+            SYSCALL_SETUID => self.sys_setuid(args[0] as u32),
+            SYSCALL_SETGID => self.sys_setgid(args[0] as u32),
+            SYSCALL_SETREUID => self.sys_setreuid(args[0] as u32, args[1] as u32),
+            SYSCALL_SETREGID => self.sys_setregid(args[0] as u32, args[1] as u32),
+            SYSCALL_SETRESUID => self.sys_setresuid(args[0] as u32, args[1] as u32, args[2] as u32),
+            SYSCALL_SETRESGID => self.sys_setresgid(args[0] as u32, args[1] as u32, args[2] as u32),
+            SYSCALL_GETRESUID => {
+                self.sys_getresuid(args[0] as *mut _, args[1] as *mut _, args[2] as *mut _)
+            }
+            SYSCALL_GETRESGID => {
+                self.sys_getresgid(args[0] as *mut _, args[1] as *mut _, args[2] as *mut _)
+            }
+            SYSCALL_GETGROUPS => self.sys_getgroups(args[0], args[1] as *mut _),
+            SYSCALL_SETGROUPS => self.sys_setgroups(args[0], args[1] as *const _),
             SYSCALL_SLEEP => self.sys_sleep(args[0]),
+            SYSCALL_NANOSLEEP => self.sys_nanosleep(args[0] as *const _, args[1] as *mut _),
+            SYSCALL_GETITIMER => self.sys_getitimer(args[0], args[1] as *mut _),
+            SYSCALL_SETITIMER => {
+                self.sys_setitimer(args[0], args[1] as *const _, args[2] as *mut _)
+            }
+            SYSCALL_TIMER_CREATE => {
+                self.sys_timer_create(args[0], args[1] as *const _, args[2] as *mut _)
+            }
+            SYSCALL_TIMER_GETTIME => self.sys_timer_gettime(args[0], args[1] as *mut _),
+            SYSCALL_TIMER_GETOVERRUN => self.sys_timer_getoverrun(args[0]),
+            SYSCALL_TIMER_SETTIME => self.sys_timer_settime(
+                args[0],
+                args[1] as u32,
+                args[2] as *const _,
+                args[3] as *mut _,
+            ),
+            SYSCALL_TIMER_DELETE => self.sys_timer_delete(args[0]),
+            SYSCALL_CLOCK_GETTIME => self.sys_clock_gettime(args[0], args[1] as *mut _),
+            SYSCALL_CLOCK_NANOSLEEP => self.sys_clock_nanosleep(
+                args[0],
+                args[1] as u32,
+                args[2] as *const _,
+                args[3] as *mut _,
+            ),
             SYSCALL_MUNMAP => self.sys_munmap(args[0].into(), args[1]),
             SYSCALL_MMAP => self.sys_mmap(args[0].into(), args[1], args[2], args[3]),
-            SYSCALL_FORK => self.sys_fork(),
-            SYSCALL_EXECVE => self.sys_exec(args[0].into(), args[1], args[2] as u32),
-            SYSCALL_WAITPID => self.sys_waitpid(args[0] as isize, args[1] as *mut i32),
+            SYSCALL_MPROTECT => self.sys_mprotect(args[0].into(), args[1], args[2]),
+            SYSCALL_CLONE => self.sys_clone(
+                args[0] as u32,
+                args[1],
+                args[2] as *mut _,
+                args[3],
+                args[4] as *mut _,
+            ),
+            SYSCALL_EXECVE => self.sys_execve(
+                args[0] as *const u8,
+                args[1] as *const usize,
+                args[2] as *const usize,
+            ),
+            SYSCALL_SPAWN => self.sys_spawn(args[0].into(), args[1], args[2] as u32),
+            SYSCALL_WAIT4 => self.sys_wait4(
+                args[0] as isize,
+                args[1] as *mut i32,
+                args[2] as u32,
+                args[3] as *mut _,
+            ),
             _ => {
                 warn!("Unsupported syscall id: {}", syscall_id);
                 -1
@@ -70,7 +245,7 @@ macro_rules! syscall_unwarp {
             Ok(value) => value,
             Err(err) => {
                 log::warn!("{}", err);
-                return EXEC_FAIL;
+                return $crate::syscall::errno::errno_of(&err);
             }
         }
     };
@@ -1,40 +1,166 @@
-use alloc::string::String;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use anyhow::anyhow;
 use bitflags::bitflags;
+use core::{mem::size_of, sync::atomic::Ordering};
+use log::warn;
+use spin::RwLock;
 
 use crate::{
+    config::CLOCK_FREQ,
     fs::inode::open_app,
     mm::{
         address::VirtAddr,
-        page_table::{translated_refmut, translated_string},
+        page_table::{
+            copy_from_user, copy_to_user, translated_refmut, translated_str, translated_str_array,
+            translated_string,
+        },
     },
     syscall_unwarp,
     task::{
-        process::Process,
-        processor::Schedule,
+        auxv::args_size,
+        loader::{load_elf, read_program},
+        policy::{NICE_MAX, NICE_MIN},
+        process::{
+            all_processes, find_process, initproc, process_group, CloneFlags, Process, WaitOptions,
+            CSIGNAL,
+        },
+        processor::{current_killed, Schedule},
+        rlimit::{RLimit, RLIMIT_CPU},
         scheduler::add_task,
-        signal::{is_handle_by_kernel, SignalFlags, MAX_SIG},
-        tigger::{ChildrenWaiter, TaskWaiter},
+        signal::{SigAction, SignalFlags, SignalStack, SIG_IGN},
+        tigger::{ChildrenWaiter, Interruptible, ThreadsWaiter, Tigger},
+        usage::UsageStat,
     },
-    timer,
+    timer::{self, TimeVal},
 };
 
-use super::{EXEC_FAIL, EXEC_SUCCEE};
+use super::{
+    errno::{E2BIG, EACCES, ECHILD, EFAULT, EINVAL, EPERM, ERESTARTSYS, ESRCH},
+    EXEC_FAIL, EXEC_SUCCEE,
+};
 
 pub(super) trait SysProcess {
     fn sys_exit(&self, code: i32) -> !;
     fn sys_yield(&self) -> isize;
-    fn sys_exec(&self, ptr: VirtAddr, len: usize, flags: u32) -> isize;
-    fn sys_fork(&self) -> isize;
+    fn sys_spawn(&self, ptr: VirtAddr, len: usize, flags: u32) -> isize;
+    fn sys_execve(&self, path: *const u8, argv: *const usize, envp: *const usize) -> isize;
+    fn sys_clone(
+        &self,
+        flags: u32,
+        stack: usize,
+        parent_tid: *mut i32,
+        tls: usize,
+        child_tid: *mut i32,
+    ) -> isize;
+    fn sys_set_tid_address(&self, tidptr: *mut i32) -> isize;
+    fn sys_gettid(&self) -> isize;
     fn sys_get_pid(&self) -> isize;
-    fn sys_waitpid(&self, pid: isize, exit_code_ptr: *mut i32) -> isize;
-    fn sys_kill(&self, pid: usize) -> isize;
-    fn sys_sigprocmask(&self, mask: u32) -> isize;
+    fn sys_wait4(&self, pid: isize, status: *mut i32, options: u32, rusage: *mut RUsage) -> isize;
+    fn sys_kill(&self, pid: isize, signum: usize) -> isize;
+    fn sys_setpgid(&self, pid: isize, pgid: isize) -> isize;
+    fn sys_getpgid(&self, pid: isize) -> isize;
+    fn sys_setsid(&self) -> isize;
+    fn sys_getsid(&self, pid: isize) -> isize;
+    fn sys_sigprocmask(
+        &self,
+        how: usize,
+        set: *const u64,
+        old_set: *mut u64,
+        sigsetsize: usize,
+    ) -> isize;
     fn sys_sigreturn(&self) -> isize;
-    fn sys_sigaction(&self, signum: u32, action: *const usize, old_action: *mut usize) -> isize;
+    fn sys_sigaltstack(&self, stack: *const SignalStack, old_stack: *mut SignalStack) -> isize;
+    fn sys_sigaction(
+        &self,
+        signum: usize,
+        action: *const SigAction,
+        old_action: *mut SigAction,
+        sigsetsize: usize,
+    ) -> isize;
+    fn sys_set_priority(&self, which: usize, who: isize, nice: isize) -> isize;
+    fn sys_get_priority(&self, which: usize, who: isize) -> isize;
+    fn sys_getrlimit(&self, resource: usize, limit: *mut RLimit) -> isize;
+    fn sys_setrlimit(&self, resource: usize, limit: *const RLimit) -> isize;
+    fn sys_prlimit(
+        &self,
+        pid: isize,
+        resource: usize,
+        new_limit: *const RLimit,
+        old_limit: *mut RLimit,
+    ) -> isize;
+    fn sys_getrusage(&self, who: isize, rusage: *mut RUsage) -> isize;
+    fn sys_times(&self, tms: *mut Tms) -> isize;
+}
+
+/// 与 Linux `struct rusage` 布局一致，只统计 CPU 时间、上下文切换和最大驻留内存，其它字段为0。
+/// 没有按需分页，用户态的缺页都是致命的，因此也没有 `ru_minflt`。
+/// Linux 不使用 `ru_nswap`，这里用它返回在硬件线程之间迁移的次数
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RUsage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    pub ru_maxrss: usize,
+    pub ru_ixrss: usize,
+    pub ru_idrss: usize,
+    pub ru_isrss: usize,
+    pub ru_minflt: usize,
+    pub ru_majflt: usize,
+    pub ru_nswap: usize,
+    pub ru_inblock: usize,
+    pub ru_oublock: usize,
+    pub ru_msgsnd: usize,
+    pub ru_msgrcv: usize,
+    pub ru_nsignals: usize,
+    pub ru_nvcsw: usize,
+    pub ru_nivcsw: usize,
+}
+
+impl From<UsageStat> for RUsage {
+    fn from(usage: UsageStat) -> Self {
+        Self {
+            ru_utime: TimeVal::from_ticks(usage.utime),
+            ru_stime: TimeVal::from_ticks(usage.stime),
+            // 以 KB 为单位
+            ru_maxrss: usage.maxrss / 1024,
+            ru_nswap: usage.migrations,
+            ru_nvcsw: usage.nvcsw,
+            ru_nivcsw: usage.nivcsw,
+            ..Default::default()
+        }
+    }
 }
 
+/// 与 Linux `struct tms` 布局一致，单位为 `CLK_TCK`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tms {
+    pub tms_utime: isize,
+    pub tms_stime: isize,
+    pub tms_cutime: isize,
+    pub tms_cstime: isize,
+}
+
+/// `times` 使用的时钟频率，与 `sysconf(_SC_CLK_TCK)` 一致
+const CLK_TCK: usize = 100;
+
+fn clock_t(ticks: usize) -> isize {
+    (ticks as u128 * CLK_TCK as u128 / CLOCK_FREQ as u128) as isize
+}
+
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// 目前只支持按进程设置优先级
+const PRIO_PROCESS: usize = 0;
+
 bitflags! {
-    struct ExecFlags: u32 {
+    struct SpawnFlags: u32 {
         const EMPTY      = 0;
         /// 继承管道
         const INHERIT    = 1 << 0;
@@ -52,20 +178,41 @@ impl<T: Schedule> SysProcess for T {
         EXEC_SUCCEE
     }
 
-    fn sys_exec(&self, ptr: VirtAddr, len: usize, flags: u32) -> isize {
-        let flags = ExecFlags::from_bits_truncate(flags);
+    /// 创建子进程运行新的程序，返回子进程的 pid
+    fn sys_spawn(&self, ptr: VirtAddr, len: usize, flags: u32) -> isize {
+        let flags = SpawnFlags::from_bits_truncate(flags);
         let current_task = self.current_task();
         let mut args =
             unsafe { syscall_unwarp!(translated_string(current_task.space(), ptr, len)) };
         let path: String = args
             .drain(..args.find('\0').unwrap_or(args.len()))
             .collect();
-        if let Some((child_process, child_task)) = open_app(&path, &args[1.min(args.len())..]) {
+        // 参数以空格或 `\0` 分隔，第一个参数为程序路径
+        let argv: Vec<String> = core::iter::once(path.as_str())
+            .chain(args.split([' ', '\0']).filter(|arg| !arg.is_empty()))
+            .map(String::from)
+            .collect();
+        syscall_unwarp!(current_task.process.check_nproc());
+        let (envs, cred, rlimits) = {
+            let inner = current_task.process.inner.read();
+            (
+                inner.environ.clone(),
+                inner.cred.clone(),
+                inner.rlimits.clone(),
+            )
+        };
+        // 与 execve 相同，参数和环境变量最多占用四分之一的用户栈
+        if args_size(&argv, &envs) > rlimits.stack_size() / 4 {
+            warn!("spawn: argument list too long");
+            return -E2BIG.0;
+        }
+        if let Some((child_process, child_task)) = open_app(&path, &argv, &envs, &cred, &rlimits) {
             unsafe { child_process.set_parent(&current_task.process) };
             let pid = child_process.get_pid();
-            if flags.contains(ExecFlags::INHERIT) {
-                child_process.inner.write().fd_table =
-                    current_task.process.inner.read().fd_table.clone();
+            if flags.contains(SpawnFlags::INHERIT) {
+                let mut fd_table = current_task.process.inner.read().fd_table.read().clone();
+                fd_table.close_on_exec();
+                child_process.inner.write().fd_table = Arc::new(RwLock::new(fd_table));
             }
             add_task(child_task);
             pid
@@ -74,116 +221,561 @@ impl<T: Schedule> SysProcess for T {
         }
     }
 
-    fn sys_waitpid(&self, pid: isize, exit_code_ptr: *mut i32) -> isize {
-        let current_task = self.current_task();
-        let current_process = &current_task.process;
-        let idx: usize;
-        let waitee_process: Process;
-        if pid == -1 {
-            self.blocking_current(ChildrenWaiter::new(current_process));
-            let children = &current_process.inner.read().tree.children;
-            if children.is_empty() {
-                return EXEC_FAIL;
-            }
-            (idx, waitee_process) = children
-                .iter()
-                .enumerate()
-                .find(|(_, child)| child.exit_code().is_some())
-                .map(|(idx, task)| (idx, task.clone()))
-                .unwrap();
-        } else if let Some(val) = unsafe { current_process.find_child(pid) } {
-            (idx, waitee_process) = val;
-            // let shared_state = waitee_task.shared_state.clone();
-            // let tigger = Tigger::new(move || {
-            //     *(shared_state.state.lock()) == TaskStatus::Exited
-            // });
-            // self.blocking_current(tigger);
-            self.blocking_current(TaskWaiter::new(&waitee_process));
-        } else {
+    /// 用新的程序替换当前进程映像，成功时不返回原程序
+    /// `envp` 为空指针时继承当前进程的环境变量
+    fn sys_execve(&self, path: *const u8, argv: *const usize, envp: *const usize) -> isize {
+        let task = self.current_task();
+        let (path, args, envs) = unsafe {
+            let space = task.space();
+            (
+                syscall_unwarp!(
+                    translated_str(space, (path as usize).into()).map_err(|err| EFAULT.with(err))
+                ),
+                syscall_unwarp!(translated_str_array(space, argv).map_err(|err| EFAULT.with(err))),
+                if envp.is_null() {
+                    task.process.inner.read().environ.clone()
+                } else {
+                    syscall_unwarp!(
+                        translated_str_array(space, envp).map_err(|err| EFAULT.with(err))
+                    )
+                },
+            )
+        };
+        let (cred, stack_size) = {
+            let inner = task.process.inner.read();
+            (inner.cred.clone(), inner.rlimits.stack_size())
+        };
+        let (data, args, metadata) = syscall_unwarp!(read_program(&path, args, &cred));
+        // 参数和环境变量最多占用四分之一的用户栈
+        if args_size(&args, &envs) > stack_size / 4 {
+            warn!("execve: argument list too long");
+            return -E2BIG.0;
+        }
+        let (memory_set, info) = syscall_unwarp!(load_elf(&data));
+        // 加载成功前不能破坏原进程，此后终止其它线程并等待它们退出
+        let others = task.process.kill_others(task.tid);
+        if !others.is_empty() {
+            self.blocking_current(ThreadsWaiter::new(others));
+        }
+        // 其它线程同时调用 exec 时当前线程可能已被终止
+        if task.is_killed() {
             return EXEC_FAIL;
         }
-        let code = waitee_process.exit_code().unwrap();
-        // info!("App {} wait app {} done!", current_task.get_pid(), waitee_task.get_pid());
-        current_task.process.inner.write().tree.children.remove(idx);
-        unsafe {
-            let ptr = syscall_unwarp!(translated_refmut(current_task.space(), exit_code_ptr));
-            *ptr = code;
+        task.process
+            .exec(&task, memory_set, info, &args, envs, &metadata);
+        // 返回值写入 a0，保持新程序的初始寄存器不变
+        unsafe { task.trap_context().reg_file.a[0] as isize }
+    }
+
+    /// `pid` 大于0时等待指定子进程，为0时等待同一进程组，为-1时等待任意子进程，
+    /// 小于-1时等待进程组 `-pid`
+    fn sys_wait4(&self, pid: isize, status: *mut i32, options: u32, rusage: *mut RUsage) -> isize {
+        let Some(options) = WaitOptions::from_bits(options) else {
+            return -EINVAL.0;
+        };
+        let task = self.current_task();
+        let process = task.process.clone();
+        let selected = |child: &Process| match pid {
+            -1 => true,
+            0 => child.get_pgid() == process.get_pgid(),
+            pid if pid < 0 => child.get_pgid() == -pid,
+            pid => child.get_pid() == pid,
+        };
+        loop {
+            let children: Vec<Process> = process
+                .inner
+                .read()
+                .tree
+                .children
+                .iter()
+                .filter(|child| selected(child))
+                .cloned()
+                .collect();
+            if children.is_empty() {
+                return -ECHILD.0;
+            }
+            let found = children.iter().find_map(|child| {
+                child
+                    .take_wait_status(options)
+                    .map(|(status, reap)| (child, status, reap))
+            });
+            if let Some((child, code, reap)) = found {
+                if reap {
+                    process.reap_child(child);
+                }
+                let space = unsafe { task.space() };
+                if !status.is_null() {
+                    unsafe {
+                        *syscall_unwarp!(
+                            translated_refmut(space, status).map_err(|err| EFAULT.with(err))
+                        ) = code;
+                    }
+                }
+                if !rusage.is_null() {
+                    unsafe {
+                        *syscall_unwarp!(
+                            translated_refmut(space, rusage).map_err(|err| EFAULT.with(err))
+                        ) = child.total_usage().into();
+                    }
+                }
+                return child.get_pid();
+            }
+            if options.contains(WaitOptions::WNOHANG) {
+                return 0;
+            }
+            // 被终止的线程尽快返回，收到信号时由信号处理决定是否重新执行
+            if current_killed() || task.shared.has_signal(&task.process.shared) {
+                return -ERESTARTSYS.0;
+            }
+            self.blocking_current(Interruptible::new(
+                &task,
+                ChildrenWaiter::new(&children, options),
+            ));
         }
-        waitee_process.get_pid()
     }
 
     fn sys_get_pid(&self) -> isize {
         self.current_task().process.get_pid()
     }
 
-    fn sys_fork(&self) -> isize {
-        let tid = self.current_task().tid;
-        let current_process = &self.current_task().process;
-        let new_process = unsafe { current_process.fork() };
-        unsafe {
-            new_process
-                .get_task(tid)
-                .unwrap()
-                .trap_context()
-                .set_return(0);
+    /// 参数顺序与 Linux 在 RISC-V 上一致，`stack` 为0时子进程与调用者使用相同的栈。
+    /// 返回子进程的 pid，或 `CLONE_THREAD` 创建的线程的 tid
+    fn sys_clone(
+        &self,
+        flags: u32,
+        stack: usize,
+        parent_tid: *mut i32,
+        tls: usize,
+        child_tid: *mut i32,
+    ) -> isize {
+        let Some(flags) = CloneFlags::from_bits(flags & !CSIGNAL) else {
+            return -EINVAL.0;
+        };
+        // 线程总是共享文件描述符表和信号处理函数，共享信号处理函数时必须共享地址空间
+        if flags.contains(CloneFlags::CLONE_THREAD)
+            && !flags.contains(CloneFlags::CLONE_SIGHAND | CloneFlags::CLONE_FILES)
+            || flags.contains(CloneFlags::CLONE_SIGHAND) && !flags.contains(CloneFlags::CLONE_VM)
+        {
+            return -EINVAL.0;
+        }
+        let task = self.current_task();
+        let process = &task.process;
+        let (child, id) = if flags.contains(CloneFlags::CLONE_THREAD) {
+            let child = process.clone_thread(&task, flags, stack, tls);
+            let tid = child.gettid();
+            (child, tid)
+        } else {
+            syscall_unwarp!(process.check_nproc());
+            let (child_process, child) =
+                syscall_unwarp!(process.clone_process(&task, flags, stack, tls));
+            (child, child_process.get_pid())
+        };
+        // 与 Linux 一致，写入 tid 失败时忽略
+        if flags.contains(CloneFlags::CLONE_PARENT_SETTID) {
+            let _ =
+                unsafe { copy_to_user(task.space(), (parent_tid as usize).into(), &(id as i32)) };
         }
-        for task in new_process.inner.read().tasks.iter_elem() {
-            add_task(task.clone());
+        if flags.contains(CloneFlags::CLONE_CHILD_SETTID) {
+            let _ =
+                unsafe { copy_to_user(child.space(), (child_tid as usize).into(), &(id as i32)) };
         }
-        new_process.get_pid()
+        if flags.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
+            child.local.borrow_mut().clear_child_tid = child_tid as usize;
+        }
+        let shared = child.process.shared.clone();
+        add_task(child);
+        // 子进程 exec 或退出前父进程不能继续使用共享的地址空间，与 Linux 一致只能被终止打断
+        if flags.contains(CloneFlags::CLONE_VFORK) {
+            let parent = task.shared.clone();
+            self.blocking_current(Tigger::new(move || {
+                !shared.vfork.load(Ordering::Acquire) || parent.killed.load(Ordering::Acquire)
+            }));
+        }
+        id
     }
 
-    fn sys_kill(&self, _pid: usize) -> isize {
-        todo!()
+    /// 返回调用线程的 tid
+    fn sys_set_tid_address(&self, tidptr: *mut i32) -> isize {
+        let task = self.current_task();
+        task.local.borrow_mut().clear_child_tid = tidptr as usize;
+        task.gettid()
     }
 
-    fn sys_sigprocmask(&self, mask: u32) -> isize {
-        let current_task = self.current_task();
-        let mut local = current_task.process.inner.write();
-        if let Some(mask) = SignalFlags::from_bits(mask) {
-            local.signal.mask = mask;
-            EXEC_SUCCEE
+    fn sys_gettid(&self) -> isize {
+        self.current_task().gettid()
+    }
+
+    /// `pid` 的含义与 `wait4` 相同，`pid` 为-1时发送给除 initproc 和自身外的所有进程，
+    /// `signum` 为0时只检查目标进程是否存在
+    fn sys_kill(&self, pid: isize, signum: usize) -> isize {
+        let flag = match signum {
+            0 => None,
+            signum => match SignalFlags::from_signum(signum) {
+                Some(flag) => Some(flag),
+                None => return -EINVAL.0,
+            },
+        };
+        let process = self.current_task().process.clone();
+        let targets: Vec<Process> = match pid {
+            pid if pid > 0 => find_process(pid).into_iter().collect(),
+            -1 => all_processes()
+                .into_iter()
+                .filter(|target| {
+                    target.get_pid() != process.get_pid()
+                        && initproc().map_or(true, |init| init.get_pid() != target.get_pid())
+                })
+                .collect(),
+            0 => process_group(process.get_pgid()),
+            pgid => process_group(-pgid),
+        };
+        let targets: Vec<Process> = targets
+            .into_iter()
+            .filter(|target| target.exit_code().is_none())
+            .collect();
+        if targets.is_empty() {
+            return -ESRCH.0;
+        }
+        // 同一会话中的进程总是可以发送 `SIGCONT`
+        let cred = process.inner.read().cred.clone();
+        let targets: Vec<Process> = targets
+            .into_iter()
+            .filter(|target| {
+                cred.can_signal(&target.inner.read().cred)
+                    || flag == Some(SignalFlags::SIGCONT) && target.get_sid() == process.get_sid()
+            })
+            .collect();
+        if targets.is_empty() {
+            return -EPERM.0;
+        }
+        if let Some(flag) = flag {
+            for target in targets {
+                target.send_signal(flag);
+            }
+        }
+        EXEC_SUCCEE
+    }
+
+    /// 只能修改调用者或其子进程的进程组，加入的进程组必须属于同一会话
+    fn sys_setpgid(&self, pid: isize, pgid: isize) -> isize {
+        if pgid < 0 {
+            return -EINVAL.0;
+        }
+        let process = self.current_task().process.clone();
+        let target = if pid == 0 || pid == process.get_pid() {
+            process.clone()
         } else {
-            EXEC_FAIL
+            let child = process
+                .inner
+                .read()
+                .tree
+                .children
+                .iter()
+                .find(|child| child.get_pid() == pid)
+                .cloned();
+            match child {
+                Some(child) => child,
+                None => return -ESRCH.0,
+            }
+        };
+        if target.get_sid() != process.get_sid() || target.is_session_leader() {
+            return -EPERM.0;
+        }
+        let pgid = if pgid == 0 { target.get_pid() } else { pgid };
+        if pgid != target.get_pid()
+            && !process_group(pgid)
+                .iter()
+                .any(|member| member.get_sid() == process.get_sid())
+        {
+            return -EPERM.0;
+        }
+        target.set_pgid(pgid);
+        EXEC_SUCCEE
+    }
+
+    fn sys_getpgid(&self, pid: isize) -> isize {
+        let target = match pid {
+            0 => Some(self.current_task().process.clone()),
+            pid => find_process(pid),
+        };
+        target.map_or(-ESRCH.0, |target| target.get_pgid())
+    }
+
+    /// 进程组组长不能创建新会话，新会话没有控制终端
+    fn sys_setsid(&self) -> isize {
+        let process = self.current_task().process.clone();
+        let pid = process.get_pid();
+        if !process_group(pid).is_empty() {
+            return -EPERM.0;
         }
+        process.set_sid(pid);
+        process.set_pgid(pid);
+        pid
+    }
+
+    fn sys_getsid(&self, pid: isize) -> isize {
+        let target = match pid {
+            0 => Some(self.current_task().process.clone()),
+            pid => find_process(pid),
+        };
+        target.map_or(-ESRCH.0, |target| target.get_sid())
     }
 
+    /// `SIGKILL` 和 `SIGSTOP` 不能被屏蔽
+    fn sys_sigprocmask(
+        &self,
+        how: usize,
+        set: *const u64,
+        old_set: *mut u64,
+        sigsetsize: usize,
+    ) -> isize {
+        if sigsetsize != size_of::<u64>() {
+            return -EINVAL.0;
+        }
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let old_mask = *task.shared.sigmask.lock();
+        if !set.is_null() {
+            let set = SignalFlags::from_sigset(syscall_unwarp!(unsafe {
+                copy_from_user::<u64>(space, (set as usize).into())
+            }
+            .map_err(|err| EFAULT.with(err))));
+            let mask = match how {
+                SIG_BLOCK => old_mask | set,
+                SIG_UNBLOCK => old_mask - set,
+                SIG_SETMASK => set,
+                _ => return -EINVAL.0,
+            };
+            *task.shared.sigmask.lock() = mask - SignalFlags::UNCATCHABLE;
+        }
+        if !old_set.is_null() {
+            syscall_unwarp!(unsafe {
+                copy_to_user(space, (old_set as usize).into(), &old_mask.to_sigset())
+            }
+            .map_err(|err| EFAULT.with(err)));
+        }
+        EXEC_SUCCEE
+    }
+
+    /// 返回值为恢复后的 a0 寄存器
     fn sys_sigreturn(&self) -> isize {
-        let current_task = self.current_task();
-        let mut local = current_task.process.inner.write();
-        // 允许接收信号
-        local.signal.global_mask = true;
-        let trap_cx = unsafe { current_task.trap_context() };
-        *trap_cx = *current_task
-            .local
-            .borrow_mut()
-            .trap_cx_backup
-            .take()
-            .unwrap();
-        // 恢复之前的a0寄存器
-        trap_cx.reg_file.a[0] as isize
-    }
-
-    fn sys_sigaction(&self, signum: u32, action: *const usize, old_action: *mut usize) -> isize {
-        if signum > MAX_SIG as u32 + 1 {
+        let task = self.current_task();
+        if let Err(err) = task.restore_signal_frame() {
+            warn!("bad signal frame: {}", err);
+            task.force_signal(SignalFlags::SIGSEGV, unsafe {
+                task.trap_context().reg_file.sp
+            });
+        }
+        unsafe { task.trap_context().reg_file.a[0] as isize }
+    }
+
+    fn sys_sigaltstack(&self, stack: *const SignalStack, old_stack: *mut SignalStack) -> isize {
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let usp = unsafe { task.trap_context().reg_file.sp };
+        let old = task.local.borrow().sigaltstack.status(usp);
+        if !stack.is_null() {
+            let stack = syscall_unwarp!(unsafe {
+                copy_from_user::<SignalStack>(space, (stack as usize).into())
+            }
+            .map_err(|err| EFAULT.with(err)));
+            syscall_unwarp!(task.set_sigaltstack(&stack));
+        }
+        if !old_stack.is_null() {
+            syscall_unwarp!(
+                unsafe { copy_to_user(space, (old_stack as usize).into(), &old) }
+                    .map_err(|err| EFAULT.with(err))
+            );
+        }
+        EXEC_SUCCEE
+    }
+
+    /// 处理函数可以是 `SIG_DFL` 或 `SIG_IGN`，`SIGKILL` 和 `SIGSTOP` 的处理方式只能查询
+    fn sys_sigaction(
+        &self,
+        signum: usize,
+        action: *const SigAction,
+        old_action: *mut SigAction,
+        sigsetsize: usize,
+    ) -> isize {
+        if sigsetsize != size_of::<u64>() {
+            return -EINVAL.0;
+        }
+        let Some(flag) = SignalFlags::from_signum(signum) else {
+            return -EINVAL.0;
+        };
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let new_action = if action.is_null() {
+            None
+        } else if flag.is_uncatchable() {
+            return -EINVAL.0;
+        } else {
+            Some(syscall_unwarp!(unsafe {
+                copy_from_user::<SigAction>(space, (action as usize).into())
+            }
+            .map_err(|err| EFAULT.with(err))))
+        };
+        let old = task.process.inner.read().signal.lock().actions[signum];
+        if !old_action.is_null() {
+            syscall_unwarp!(
+                unsafe { copy_to_user(space, (old_action as usize).into(), &old) }
+                    .map_err(|err| EFAULT.with(err))
+            );
+        }
+        if let Some(new_action) = new_action {
+            task.process.inner.read().signal.lock().actions[signum] = new_action;
+            if new_action.handler == SIG_IGN {
+                // 被忽略的待处理信号直接丢弃
+                task.process.shared.signals.lock().remove(flag);
+                for task in task.process.inner.read().tasks.iter_elem() {
+                    task.shared.signals.lock().remove(flag);
+                }
+            }
+        }
+        EXEC_SUCCEE
+    }
+
+    /// 只有 root 可以降低 nice 值或修改其它用户的进程
+    fn sys_set_priority(&self, which: usize, who: isize, nice: isize) -> isize {
+        if which != PRIO_PROCESS {
             return EXEC_FAIL;
         }
-        let current_task = self.current_task();
-        let mut local = current_task.process.inner.write();
-        if let Some(flag) = SignalFlags::from_bits(1 << signum) {
-            if is_handle_by_kernel(flag) || action as usize == 0 {
-                return EXEC_FAIL;
-            }
-            let act = &mut local.signal.actions[signum as usize];
-            unsafe {
-                let ptr = syscall_unwarp!(translated_refmut(current_task.space(), old_action));
-                *ptr = *act;
-            }
-            *act = action as usize;
-            EXEC_SUCCEE
+        let task = self.current_task();
+        let process = match who {
+            0 => task.process.clone(),
+            pid => match find_process(pid) {
+                Some(process) => process,
+                None => return -ESRCH.0,
+            },
+        };
+        let cred = task.process.inner.read().cred.clone();
+        if !cred.can_renice(&process.inner.read().cred) {
+            return -EPERM.0;
+        }
+        let nice = (nice as i32).clamp(NICE_MIN, NICE_MAX);
+        let current = syscall_unwarp!(process.get_nice().ok_or(anyhow!("process exited")));
+        if nice < current && !cred.is_root() {
+            return -EACCES.0;
+        }
+        process.set_nice(nice);
+        EXEC_SUCCEE
+    }
+
+    /// 与 Linux 系统调用一致，返回 `20 - nice`
+    fn sys_get_priority(&self, which: usize, who: isize) -> isize {
+        if which != PRIO_PROCESS {
+            return EXEC_FAIL;
+        }
+        let process = match who {
+            0 => self.current_task().process.clone(),
+            pid => syscall_unwarp!(find_process(pid).ok_or(anyhow!("no such process"))),
+        };
+        let nice = syscall_unwarp!(process.get_nice().ok_or(anyhow!("process exited")));
+        20 - nice as isize
+    }
+
+    fn sys_getrlimit(&self, resource: usize, limit: *mut RLimit) -> isize {
+        self.sys_prlimit(0, resource, core::ptr::null(), limit)
+    }
+
+    fn sys_setrlimit(&self, resource: usize, limit: *const RLimit) -> isize {
+        self.sys_prlimit(0, resource, limit, core::ptr::null_mut())
+    }
+
+    /// `pid` 为0时为当前进程，`new_limit` 和 `old_limit` 为空时不修改或不返回限制
+    fn sys_prlimit(
+        &self,
+        pid: isize,
+        resource: usize,
+        new_limit: *const RLimit,
+        old_limit: *mut RLimit,
+    ) -> isize {
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let target = match pid {
+            0 => task.process.clone(),
+            pid => match find_process(pid) {
+                Some(process) => process,
+                None => return -ESRCH.0,
+            },
+        };
+        let cred = task.process.inner.read().cred.clone();
+        if target.get_pid() != task.process.get_pid()
+            && !cred.can_control(&target.inner.read().cred)
+        {
+            return -EPERM.0;
+        }
+        let new_limit = if new_limit.is_null() {
+            None
         } else {
-            EXEC_FAIL
+            Some(syscall_unwarp!(unsafe {
+                copy_from_user::<RLimit>(space, (new_limit as usize).into())
+            }
+            .map_err(|err| EFAULT.with(err))))
+        };
+        let old = {
+            let mut inner = target.inner.write();
+            match new_limit {
+                Some(limit) => syscall_unwarp!(inner.rlimits.set(resource, limit, cred.is_root())),
+                None => syscall_unwarp!(inner.rlimits.get(resource)),
+            }
+        };
+        if new_limit.is_some() && resource == RLIMIT_CPU {
+            target.update_cpu_limit();
+        }
+        if !old_limit.is_null() {
+            syscall_unwarp!(
+                unsafe { copy_to_user(space, (old_limit as usize).into(), &old) }
+                    .map_err(|err| EFAULT.with(err))
+            );
+        }
+        EXEC_SUCCEE
+    }
+
+    fn sys_getrusage(&self, who: isize, rusage: *mut RUsage) -> isize {
+        let task = self.current_task();
+        let process = &task.process;
+        let usage = match who {
+            RUSAGE_SELF => {
+                process.update_rss();
+                process.shared.usage.stat()
+            }
+            RUSAGE_CHILDREN => *process.shared.children_usage.lock(),
+            RUSAGE_THREAD => {
+                // 线程共享地址空间，最大驻留内存使用进程的值
+                process.update_rss();
+                UsageStat {
+                    maxrss: process.shared.usage.stat().maxrss,
+                    ..task.shared.usage.stat()
+                }
+            }
+            _ => return -EINVAL.0,
+        };
+        syscall_unwarp!(unsafe {
+            copy_to_user(task.space(), (rusage as usize).into(), &RUsage::from(usage))
+        }
+        .map_err(|err| EFAULT.with(err)));
+        EXEC_SUCCEE
+    }
+
+    /// 返回启动以来经过的时间
+    fn sys_times(&self, tms: *mut Tms) -> isize {
+        let task = self.current_task();
+        if !tms.is_null() {
+            let (utime, stime) = task.process.shared.usage.times();
+            let children = *task.process.shared.children_usage.lock();
+            let times = Tms {
+                tms_utime: clock_t(utime),
+                tms_stime: clock_t(stime),
+                tms_cutime: clock_t(children.utime),
+                tms_cstime: clock_t(children.stime),
+            };
+            syscall_unwarp!(
+                unsafe { copy_to_user(task.space(), (tms as usize).into(), &times) }
+                    .map_err(|err| EFAULT.with(err))
+            );
         }
+        clock_t(timer::get_time())
     }
 }
 
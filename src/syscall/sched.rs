@@ -0,0 +1,260 @@
+use alloc::sync::Arc;
+use core::mem::size_of;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    ipi::call_on,
+    mm::page_table::translated_refmut,
+    sbi::get_hartid,
+    syscall_unwarp,
+    task::{
+        policy::{ALL_HARTS, NICE_MAX, NICE_MIN},
+        process::find_process,
+        processor::Schedule,
+        rt::{
+            DeadlineParam, SchedClass, RT_PRIO_MAX, RT_PRIO_MIN, SCHED_DEADLINE, SCHED_FIFO,
+            SCHED_NORMAL, SCHED_RR,
+        },
+        scheduler::get_processor,
+        tcb::Task,
+    },
+    timer::{ns_to_ticks, ticks_to_ns},
+};
+
+use super::{
+    errno::{EBUSY, EPERM},
+    EXEC_FAIL, EXEC_SUCCEE,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SchedParam {
+    pub sched_priority: i32,
+}
+
+/// 与 Linux `struct sched_attr` 布局一致，时间单位为纳秒
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SchedAttr {
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    pub sched_runtime: u64,
+    pub sched_deadline: u64,
+    pub sched_period: u64,
+}
+
+pub(super) trait SysSched {
+    fn sys_sched_setparam(&self, pid: isize, param: *const SchedParam) -> isize;
+    fn sys_sched_setscheduler(&self, pid: isize, policy: u32, param: *const SchedParam) -> isize;
+    fn sys_sched_getscheduler(&self, pid: isize) -> isize;
+    fn sys_sched_getparam(&self, pid: isize, param: *mut SchedParam) -> isize;
+    fn sys_sched_get_priority_max(&self, policy: u32) -> isize;
+    fn sys_sched_get_priority_min(&self, policy: u32) -> isize;
+    fn sys_sched_setattr(&self, pid: isize, attr: *const SchedAttr, flags: u32) -> isize;
+    fn sys_sched_getattr(&self, pid: isize, attr: *mut SchedAttr, size: u32, flags: u32) -> isize;
+    fn sys_sched_setaffinity(&self, pid: isize, len: usize, mask: *const usize) -> isize;
+    fn sys_sched_getaffinity(&self, pid: isize, len: usize, mask: *mut usize) -> isize;
+}
+
+/// `pid` 为0时为当前线程，否则为对应进程的主线程
+fn target_task<T: Schedule>(sched: &T, pid: isize) -> Result<Task> {
+    if pid == 0 {
+        return Ok(sched.current_task());
+    }
+    find_process(pid)
+        .and_then(|process| process.get_task(0))
+        .ok_or(anyhow!("no such process {}", pid))
+}
+
+/// 调度类改变后由目标任务所在的处理器重新决定是否需要调度时钟，
+/// 调度时钟只能由硬件线程自己设置
+fn update_tick_of(task: &Task) {
+    let last_hart = task.sched.lock().last_hart;
+    match last_hart {
+        Some(hartid) if hartid != get_hartid() => {
+            call_on(hartid, || get_processor().update_tick());
+        }
+        _ => get_processor().update_tick(),
+    }
+}
+
+/// 修改其它进程需要与目标相同的用户，只有 root 可以进入实时和截止期限调度类
+fn check_permission(current: &Task, target: &Task, class: Option<SchedClass>) -> Result<()> {
+    let cred = current.process.inner.read().cred.clone();
+    if !Arc::ptr_eq(&current.process, &target.process)
+        && !cred.can_renice(&target.process.inner.read().cred)
+    {
+        return Err(EPERM.with("cannot change sched param of other user's process"));
+    }
+    if class.is_some_and(|class| class != SchedClass::Normal) && !cred.is_root() {
+        return Err(EPERM.with("realtime sched class requires root"));
+    }
+    Ok(())
+}
+
+impl<T: Schedule> SysSched for T {
+    fn sys_sched_setparam(&self, pid: isize, param: *const SchedParam) -> isize {
+        let task = syscall_unwarp!(target_task(self, pid));
+        let policy = task.sched.lock().class.policy();
+        self.sys_sched_setscheduler(pid, policy, param)
+    }
+
+    fn sys_sched_setscheduler(&self, pid: isize, policy: u32, param: *const SchedParam) -> isize {
+        let task = syscall_unwarp!(target_task(self, pid));
+        let current = self.current_task();
+        let param = unsafe {
+            *syscall_unwarp!(translated_refmut(current.space(), param as *mut SchedParam))
+        };
+        let class = syscall_unwarp!(SchedClass::from_policy(policy, param.sched_priority as u32));
+        syscall_unwarp!(check_permission(&current, &task, Some(class)));
+        syscall_unwarp!(task.sched.lock().set_class(class));
+        update_tick_of(&task);
+        EXEC_SUCCEE
+    }
+
+    fn sys_sched_getscheduler(&self, pid: isize) -> isize {
+        let task = syscall_unwarp!(target_task(self, pid));
+        let policy = task.sched.lock().class.policy();
+        policy as isize
+    }
+
+    fn sys_sched_getparam(&self, pid: isize, param: *mut SchedParam) -> isize {
+        let task = syscall_unwarp!(target_task(self, pid));
+        let sched_priority = task.sched.lock().class.rt_priority() as i32;
+        let current = self.current_task();
+        unsafe {
+            let param = syscall_unwarp!(translated_refmut(current.space(), param));
+            *param = SchedParam { sched_priority };
+        }
+        EXEC_SUCCEE
+    }
+
+    fn sys_sched_get_priority_max(&self, policy: u32) -> isize {
+        match policy {
+            SCHED_FIFO | SCHED_RR => RT_PRIO_MAX as isize,
+            SCHED_NORMAL | SCHED_DEADLINE => 0,
+            _ => EXEC_FAIL,
+        }
+    }
+
+    fn sys_sched_get_priority_min(&self, policy: u32) -> isize {
+        match policy {
+            SCHED_FIFO | SCHED_RR => RT_PRIO_MIN as isize,
+            SCHED_NORMAL | SCHED_DEADLINE => 0,
+            _ => EXEC_FAIL,
+        }
+    }
+
+    fn sys_sched_setattr(&self, pid: isize, attr: *const SchedAttr, _flags: u32) -> isize {
+        let task = syscall_unwarp!(target_task(self, pid));
+        let current = self.current_task();
+        let attr =
+            unsafe { *syscall_unwarp!(translated_refmut(current.space(), attr as *mut SchedAttr)) };
+        let class = if attr.sched_policy == SCHED_DEADLINE {
+            SchedClass::Deadline(syscall_unwarp!(DeadlineParam::new(
+                ns_to_ticks(attr.sched_runtime as usize),
+                ns_to_ticks(attr.sched_deadline as usize),
+                ns_to_ticks(attr.sched_period as usize),
+            )))
+        } else {
+            syscall_unwarp!(SchedClass::from_policy(
+                attr.sched_policy,
+                attr.sched_priority
+            ))
+        };
+        syscall_unwarp!(check_permission(&current, &task, Some(class)));
+        let nice = attr.sched_nice.clamp(NICE_MIN, NICE_MAX);
+        let is_root = current.process.inner.read().cred.is_root();
+        let mut sched = task.sched.lock();
+        // 与 setpriority 相同，只有 root 可以降低 nice 值
+        if class == SchedClass::Normal && nice < sched.nice && !is_root {
+            return -EPERM.0;
+        }
+        syscall_unwarp!(sched.set_class(class));
+        if class == SchedClass::Normal {
+            sched.nice = nice;
+        }
+        drop(sched);
+        update_tick_of(&task);
+        EXEC_SUCCEE
+    }
+
+    fn sys_sched_getattr(&self, pid: isize, attr: *mut SchedAttr, size: u32, _flags: u32) -> isize {
+        if (size as usize) < size_of::<SchedAttr>() {
+            return EXEC_FAIL;
+        }
+        let task = syscall_unwarp!(target_task(self, pid));
+        let (class, nice) = {
+            let sched = task.sched.lock();
+            (sched.class, sched.nice)
+        };
+        let (runtime, deadline, period) = match class {
+            SchedClass::Deadline(param) => (param.runtime, param.deadline, param.period),
+            _ => (0, 0, 0),
+        };
+        let current = self.current_task();
+        unsafe {
+            let attr = syscall_unwarp!(translated_refmut(current.space(), attr));
+            *attr = SchedAttr {
+                size: size_of::<SchedAttr>() as u32,
+                sched_policy: class.policy(),
+                sched_flags: 0,
+                sched_nice: nice,
+                sched_priority: class.rt_priority(),
+                sched_runtime: ticks_to_ns(runtime) as u64,
+                sched_deadline: ticks_to_ns(deadline) as u64,
+                sched_period: ticks_to_ns(period) as u64,
+            };
+        }
+        EXEC_SUCCEE
+    }
+
+    fn sys_sched_setaffinity(&self, pid: isize, len: usize, mask: *const usize) -> isize {
+        if len < size_of::<usize>() {
+            return EXEC_FAIL;
+        }
+        let task = syscall_unwarp!(target_task(self, pid));
+        let current = self.current_task();
+        let mask =
+            unsafe { *syscall_unwarp!(translated_refmut(current.space(), mask as *mut usize)) };
+        if mask & ALL_HARTS == 0 {
+            return EXEC_FAIL;
+        }
+        syscall_unwarp!(check_permission(&current, &task, None));
+        {
+            let mut sched = task.sched.lock();
+            // 截止期限任务不能离开预留带宽的硬件线程
+            if sched
+                .dl_hart
+                .is_some_and(|hartid| mask & (1 << hartid) == 0)
+            {
+                return -EBUSY.0;
+            }
+            sched.affinity = mask & ALL_HARTS;
+        }
+        // 当前任务不再允许在此处理器上运行时立即迁移
+        if !self.current_task().sched.lock().allowed_on(get_hartid()) {
+            self.yield_();
+        }
+        EXEC_SUCCEE
+    }
+
+    /// 返回写入的掩码字节数
+    fn sys_sched_getaffinity(&self, pid: isize, len: usize, mask: *mut usize) -> isize {
+        if len < size_of::<usize>() {
+            return EXEC_FAIL;
+        }
+        let task = syscall_unwarp!(target_task(self, pid));
+        let affinity = task.sched.lock().affinity;
+        let current = self.current_task();
+        unsafe {
+            let mask = syscall_unwarp!(translated_refmut(current.space(), mask));
+            *mask = affinity;
+        }
+        size_of::<usize>() as isize
+    }
+}
@@ -1,16 +1,362 @@
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
+
 use crate::{
-    task::{processor::Schedule, tigger::Timer},
-    timer::get_time_ms,
+    mm::page_table::{copy_from_user, copy_to_user, translated_refmut},
+    syscall_unwarp,
+    task::{
+        itimer::{
+            signal_callback, ITimerSpec, ITimerVal, PosixTimer, ITIMER_PROF, ITIMER_REAL,
+            ITIMER_VIRTUAL,
+        },
+        processor::Schedule,
+        signal::SignalFlags,
+        tigger::{Interruptible, Timer},
+    },
+    timer::{get_time, get_time_ms, get_time_ns, ns_to_ticks, ticks_to_ns, TimeSpec},
+};
+
+use super::{
+    errno::{EFAULT, EINTR, EINVAL},
+    EXEC_SUCCEE,
 };
 
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_BOOTTIME: usize = 7;
+
+/// `clock_nanosleep` 的 `flags`，表示 `req` 为绝对时间
+const TIMER_ABSTIME: u32 = 1;
+
+/// 没有实时时钟，所有时钟都从启动时开始计时
+fn clock_supported(clock_id: usize) -> bool {
+    matches!(
+        clock_id,
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_BOOTTIME
+    )
+}
+
 pub(super) trait SysSync {
     fn sys_sleep(&self, ms: usize) -> isize;
+    fn sys_nanosleep(&self, req: *const TimeSpec, rem: *mut TimeSpec) -> isize;
+    fn sys_clock_gettime(&self, clock_id: usize, tp: *mut TimeSpec) -> isize;
+    fn sys_clock_nanosleep(
+        &self,
+        clock_id: usize,
+        flags: u32,
+        req: *const TimeSpec,
+        rem: *mut TimeSpec,
+    ) -> isize;
+    fn sys_getitimer(&self, which: usize, value: *mut ITimerVal) -> isize;
+    fn sys_setitimer(
+        &self,
+        which: usize,
+        new_value: *const ITimerVal,
+        old_value: *mut ITimerVal,
+    ) -> isize;
+    fn sys_timer_create(&self, clock_id: usize, sevp: *const SigEvent, timer_id: *mut i32)
+        -> isize;
+    fn sys_timer_settime(
+        &self,
+        timer_id: usize,
+        flags: u32,
+        new_value: *const ITimerSpec,
+        old_value: *mut ITimerSpec,
+    ) -> isize;
+    fn sys_timer_gettime(&self, timer_id: usize, value: *mut ITimerSpec) -> isize;
+    fn sys_timer_getoverrun(&self, timer_id: usize) -> isize;
+    fn sys_timer_delete(&self, timer_id: usize) -> isize;
 }
 
+/// 与 Linux `struct sigevent` 布局一致
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigEvent {
+    pub value: usize,
+    pub signo: i32,
+    pub notify: i32,
+    _pad: [i32; 12],
+}
+
+/// 到期时发送信号
+const SIGEV_SIGNAL: i32 = 0;
+/// 到期时不通知
+const SIGEV_NONE: i32 = 1;
+
 impl<T: Schedule> SysSync for T {
+    /// 以毫秒为单位睡眠，返回实际睡眠的毫秒数
     fn sys_sleep(&self, ms: usize) -> isize {
         let time = get_time_ms();
         self.blocking_current(Timer::new(ms));
         (get_time_ms() - time) as isize
     }
+
+    /// 被信号中断时返回 `EINTR`，`rem` 不为空时写入剩余的时间
+    fn sys_nanosleep(&self, req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+        let task = self.current_task();
+        let req = unsafe {
+            *syscall_unwarp!(translated_refmut(task.space(), req as *mut TimeSpec)
+                .map_err(|err| EFAULT.with(err)))
+        };
+        if !req.is_valid() {
+            return -EINVAL.0;
+        }
+        let deadline = get_time().saturating_add(ns_to_ticks(req.as_ns()));
+        self.blocking_current(Interruptible::new(&task, Timer::until(deadline)));
+        let now = get_time();
+        if now < deadline {
+            if !rem.is_null() {
+                unsafe {
+                    *syscall_unwarp!(
+                        translated_refmut(task.space(), rem).map_err(|err| EFAULT.with(err))
+                    ) = TimeSpec::from_ns(ticks_to_ns(deadline - now));
+                }
+            }
+            return -EINTR.0;
+        }
+        EXEC_SUCCEE
+    }
+
+    fn sys_clock_gettime(&self, clock_id: usize, tp: *mut TimeSpec) -> isize {
+        if !clock_supported(clock_id) {
+            return -EINVAL.0;
+        }
+        let task = self.current_task();
+        unsafe {
+            let tp = syscall_unwarp!(
+                translated_refmut(task.space(), tp).map_err(|err| EFAULT.with(err))
+            );
+            *tp = TimeSpec::from_ns(get_time_ns());
+        }
+        EXEC_SUCCEE
+    }
+
+    fn sys_clock_nanosleep(
+        &self,
+        clock_id: usize,
+        flags: u32,
+        req: *const TimeSpec,
+        rem: *mut TimeSpec,
+    ) -> isize {
+        if !clock_supported(clock_id) {
+            return -EINVAL.0;
+        }
+        if flags & TIMER_ABSTIME == 0 {
+            return self.sys_nanosleep(req, rem);
+        }
+        let task = self.current_task();
+        let req = unsafe {
+            *syscall_unwarp!(translated_refmut(task.space(), req as *mut TimeSpec)
+                .map_err(|err| EFAULT.with(err)))
+        };
+        if !req.is_valid() {
+            return -EINVAL.0;
+        }
+        // 绝对时间的睡眠被中断时不需要写入剩余时间
+        let deadline = ns_to_ticks(req.as_ns());
+        self.blocking_current(Interruptible::new(&task, Timer::until(deadline)));
+        if get_time() < deadline {
+            return -EINTR.0;
+        }
+        EXEC_SUCCEE
+    }
+
+    fn sys_getitimer(&self, which: usize, value: *mut ITimerVal) -> isize {
+        let task = self.current_task();
+        let (utime, stime) = task.process.shared.usage.times();
+        let current = {
+            let timers = task.process.shared.timers.lock();
+            match which {
+                ITIMER_REAL => timers.real.get(),
+                ITIMER_VIRTUAL => timers.virt.get(utime),
+                ITIMER_PROF => timers.prof.get(utime + stime),
+                _ => return -EINVAL.0,
+            }
+        };
+        syscall_unwarp!(unsafe {
+            copy_to_user(
+                task.space(),
+                (value as usize).into(),
+                &ITimerVal::from_ticks(current),
+            )
+        }
+        .map_err(|err| EFAULT.with(err)));
+        EXEC_SUCCEE
+    }
+
+    /// 定时器到期时分别发送 `SIGALRM`、`SIGVTALRM` 和 `SIGPROF`
+    fn sys_setitimer(
+        &self,
+        which: usize,
+        new_value: *const ITimerVal,
+        old_value: *mut ITimerVal,
+    ) -> isize {
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let new_value = syscall_unwarp!(unsafe {
+            copy_from_user::<ITimerVal>(space, (new_value as usize).into())
+        }
+        .map_err(|err| EFAULT.with(err)));
+        if !new_value.is_valid() {
+            return -EINVAL.0;
+        }
+        let (value, interval) = new_value.as_ticks();
+        let process = &task.process;
+        let (utime, stime) = process.shared.usage.times();
+        let old = {
+            let mut timers = process.shared.timers.lock();
+            match which {
+                ITIMER_REAL => {
+                    let deadline = if value == 0 {
+                        0
+                    } else {
+                        get_time().saturating_add(value)
+                    };
+                    let callback = signal_callback(Arc::downgrade(process), SignalFlags::SIGALRM);
+                    timers.real.set(deadline, interval, callback)
+                }
+                ITIMER_VIRTUAL => timers.virt.set(utime, value, interval),
+                ITIMER_PROF => timers.prof.set(utime + stime, value, interval),
+                _ => return -EINVAL.0,
+            }
+        };
+        if !old_value.is_null() {
+            syscall_unwarp!(unsafe {
+                copy_to_user(
+                    space,
+                    (old_value as usize).into(),
+                    &ITimerVal::from_ticks(old),
+                )
+            }
+            .map_err(|err| EFAULT.with(err)));
+        }
+        EXEC_SUCCEE
+    }
+
+    /// `sevp` 为空时到期发送 `SIGALRM`
+    fn sys_timer_create(
+        &self,
+        clock_id: usize,
+        sevp: *const SigEvent,
+        timer_id: *mut i32,
+    ) -> isize {
+        if !clock_supported(clock_id) {
+            return -EINVAL.0;
+        }
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let signal = if sevp.is_null() {
+            Some(SignalFlags::SIGALRM)
+        } else {
+            let event = syscall_unwarp!(unsafe {
+                copy_from_user::<SigEvent>(space, (sevp as usize).into())
+            }
+            .map_err(|err| EFAULT.with(err)));
+            match event.notify {
+                SIGEV_SIGNAL => match SignalFlags::from_signum(event.signo as usize) {
+                    Some(signal) => Some(signal),
+                    None => return -EINVAL.0,
+                },
+                SIGEV_NONE => None,
+                _ => return -EINVAL.0,
+            }
+        };
+        let id = task
+            .process
+            .shared
+            .timers
+            .lock()
+            .posix
+            .push(PosixTimer::new(signal));
+        syscall_unwarp!(
+            unsafe { copy_to_user(space, (timer_id as usize).into(), &(id as i32)) }.map_err(
+                |err| {
+                    task.process.shared.timers.lock().posix.remove(id);
+                    EFAULT.with(err)
+                }
+            )
+        );
+        EXEC_SUCCEE
+    }
+
+    /// `flags` 包含 `TIMER_ABSTIME` 时 `new_value` 为绝对时间
+    fn sys_timer_settime(
+        &self,
+        timer_id: usize,
+        flags: u32,
+        new_value: *const ITimerSpec,
+        old_value: *mut ITimerSpec,
+    ) -> isize {
+        let task = self.current_task();
+        let space = unsafe { task.space() };
+        let new_value = syscall_unwarp!(unsafe {
+            copy_from_user::<ITimerSpec>(space, (new_value as usize).into())
+        }
+        .map_err(|err| EFAULT.with(err)));
+        if !new_value.is_valid() {
+            return -EINVAL.0;
+        }
+        let (value, interval) = new_value.as_ticks();
+        let deadline = match value {
+            0 => 0,
+            // 已经过去的绝对时间在下一次时钟中断时到期
+            value if flags & TIMER_ABSTIME != 0 => value,
+            value => get_time().saturating_add(value),
+        };
+        let process = Arc::downgrade(&task.process);
+        let old = {
+            let mut timers = task.process.shared.timers.lock();
+            let Some(timer) = timers.posix.get_mut(timer_id) else {
+                return -EINVAL.0;
+            };
+            let callback = timer.callback(process);
+            timer.timer.set(deadline, interval, callback)
+        };
+        if !old_value.is_null() {
+            syscall_unwarp!(unsafe {
+                copy_to_user(
+                    space,
+                    (old_value as usize).into(),
+                    &ITimerSpec::from_ticks(old),
+                )
+            }
+            .map_err(|err| EFAULT.with(err)));
+        }
+        EXEC_SUCCEE
+    }
+
+    fn sys_timer_gettime(&self, timer_id: usize, value: *mut ITimerSpec) -> isize {
+        let task = self.current_task();
+        let current = match task.process.shared.timers.lock().posix.get(timer_id) {
+            Some(timer) => timer.timer.get(),
+            None => return -EINVAL.0,
+        };
+        syscall_unwarp!(unsafe {
+            copy_to_user(
+                task.space(),
+                (value as usize).into(),
+                &ITimerSpec::from_ticks(current),
+            )
+        }
+        .map_err(|err| EFAULT.with(err)));
+        EXEC_SUCCEE
+    }
+
+    fn sys_timer_getoverrun(&self, timer_id: usize) -> isize {
+        let task = self.current_task();
+        match task.process.shared.timers.lock().posix.get(timer_id) {
+            Some(timer) => timer.overrun.load(Ordering::Relaxed) as isize,
+            None => -EINVAL.0,
+        }
+    }
+
+    fn sys_timer_delete(&self, timer_id: usize) -> isize {
+        let task = self.current_task();
+        let timer = task.process.shared.timers.lock().posix.remove(timer_id);
+        match timer {
+            Some(_) => EXEC_SUCCEE,
+            None => -EINVAL.0,
+        }
+    }
 }
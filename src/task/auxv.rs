@@ -0,0 +1,136 @@
+use core::mem::size_of;
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    config::PAGE_SIZE,
+    mm::{
+        address::VirtAddr,
+        memory_set::{ElfLoadInfo, MemorySet},
+        page_table::{translated_byte_buffer, BufferHandle},
+    },
+    timer::get_time,
+};
+
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_ENTRY: usize = 9;
+pub const AT_HWCAP: usize = 16;
+pub const AT_RANDOM: usize = 25;
+
+/// 支持的扩展 IMAFDC，每个字母对应一位
+const HWCAP: usize = {
+    let mut hwcap = 0;
+    let isa = b"imafdc";
+    let mut i = 0;
+    while i < isa.len() {
+        hwcap |= 1 << (isa[i] - b'a');
+        i += 1;
+    }
+    hwcap
+};
+
+/// 按 RISC-V psABI 向下增长构造用户栈
+struct StackWriter<'a> {
+    memory_set: &'a MemorySet,
+    sp: VirtAddr,
+}
+
+impl StackWriter<'_> {
+    fn push_bytes(&mut self, bytes: &[u8]) -> usize {
+        self.sp = self.sp.offset(-(bytes.len() as isize));
+        let buffer = unsafe {
+            BufferHandle::new(
+                translated_byte_buffer(self.memory_set, self.sp, bytes.len()).unwrap(),
+            )
+        };
+        for (dst, src) in buffer.into_iter().zip(bytes.iter()) {
+            *dst = *src;
+        }
+        self.sp.into()
+    }
+
+    fn push_str(&mut self, s: &str) -> usize {
+        self.push_bytes(&[0]);
+        self.push_bytes(s.as_bytes())
+    }
+
+    fn push_usizes(&mut self, values: &[usize]) {
+        for value in values.iter().rev() {
+            self.push_bytes(&value.to_ne_bytes());
+        }
+    }
+
+    fn align(&mut self, align: usize) {
+        self.sp = (usize::from(self.sp) & !(align - 1)).into();
+    }
+}
+
+/// 参数和环境变量在用户栈上占用的字节数
+pub fn args_size(args: &[String], envs: &[String]) -> usize {
+    args.iter()
+        .chain(envs.iter())
+        .map(|s| s.len() + 1 + size_of::<usize>())
+        .sum()
+}
+
+/// 生成 `AT_RANDOM` 指向的16字节随机数
+fn random_bytes() -> [u8; 16] {
+    let mut state = get_time() as u64 | 1;
+    let mut result = [0u8; 16];
+    for chunk in result.chunks_mut(size_of::<u64>()) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        chunk.copy_from_slice(&state.to_ne_bytes());
+    }
+    result
+}
+
+/// 在 `usp` 下方依次压入参数和环境变量字符串、随机数、辅助向量、`envp`、`argv` 和 `argc`，
+/// 返回新的栈顶，即 `argc` 的地址
+pub fn push_startup(
+    memory_set: &MemorySet,
+    usp: VirtAddr,
+    info: &ElfLoadInfo,
+    args: &[String],
+    envs: &[String],
+) -> VirtAddr {
+    let mut stack = StackWriter {
+        memory_set,
+        sp: usp,
+    };
+    let envp: Vec<usize> = envs.iter().map(|env| stack.push_str(env)).collect();
+    let argv: Vec<usize> = args.iter().map(|arg| stack.push_str(arg)).collect();
+    let random = stack.push_bytes(&random_bytes());
+    stack.align(size_of::<usize>());
+    let auxv = [
+        (AT_PHDR, info.phdr),
+        (AT_PHENT, info.phent),
+        (AT_PHNUM, info.phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, info.base),
+        (AT_ENTRY, info.entry),
+        (AT_HWCAP, HWCAP),
+        (AT_RANDOM, random),
+        (AT_NULL, 0),
+    ];
+    // 保证最终的栈顶16字节对齐
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + auxv.len() * 2;
+    stack.sp = stack.sp.offset(-((words * size_of::<usize>()) as isize));
+    stack.align(16);
+    stack.sp = stack.sp.offset((words * size_of::<usize>()) as isize);
+    for (key, value) in auxv.iter().rev() {
+        stack.push_usizes(&[*key, *value]);
+    }
+    stack.push_usizes(&[0]);
+    stack.push_usizes(&envp);
+    stack.push_usizes(&[0]);
+    stack.push_usizes(&argv);
+    stack.push_usizes(&[args.len()]);
+    stack.sp
+}
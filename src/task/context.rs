@@ -8,7 +8,7 @@ use crate::{
     KERNEL_STACK,
 };
 
-use super::processor::switch_trampoline;
+use super::{kthread::kthread_entry, processor::switch_trampoline};
 
 pub struct Context {
     pub task_cx: TaskContext,
@@ -59,6 +59,17 @@ impl TaskContext {
         result
     }
 
+    /// 切换后从 `kthread_entry` 开始运行，`s0` 保存线程入口
+    pub fn kernel_thread(ksp: usize, entry: usize) -> Self {
+        let mut result = Self {
+            ra: kthread_entry as usize,
+            ksp,
+            s: [0; 12],
+        };
+        result.s[0] = entry;
+        result
+    }
+
     pub fn switch_trampoline(hartid: usize) -> Self {
         let ksp =
             unsafe { &KERNEL_STACK as *const u8 as usize } + (hartid + 1) * KERNEL_INIT_STACK_SIZE;
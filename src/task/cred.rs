@@ -0,0 +1,245 @@
+use alloc::vec::Vec;
+
+use anyhow::{anyhow, Result};
+use bitflags::bitflags;
+
+use crate::{
+    fs::inode::{FileMetadata, S_ISGID, S_ISUID, S_IXGRP},
+    syscall::errno::{EACCES, EPERM},
+};
+
+/// 附加组的最大数量
+pub const NGROUPS_MAX: usize = 65536;
+
+bitflags! {
+    /// 文件访问权限，与权限位中每一组的含义一致
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Access: u32 {
+        const X = 1 << 0;
+        const W = 1 << 1;
+        const R = 1 << 2;
+    }
+}
+
+/// 实际、有效和保存的 id
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Ids {
+    pub real: u32,
+    pub effective: u32,
+    pub saved: u32,
+}
+
+impl Ids {
+    fn contains(&self, id: u32) -> bool {
+        id == self.real || id == self.effective || id == self.saved
+    }
+
+    fn all(&self, id: u32) -> bool {
+        id == self.real && id == self.effective && id == self.saved
+    }
+
+    /// 特权进程修改全部 id，否则只能将有效 id 改为实际或保存的 id
+    fn set(&mut self, id: u32, privileged: bool) -> Result<()> {
+        if privileged {
+            *self = Self {
+                real: id,
+                effective: id,
+                saved: id,
+            };
+        } else if id == self.real || id == self.saved {
+            self.effective = id;
+        } else {
+            return Err(EPERM.with(anyhow!("cannot change effective id to {}", id)));
+        }
+        Ok(())
+    }
+
+    /// 实际 id 只能改为实际或有效 id，有效 id 只能改为三者之一。
+    /// 修改了实际 id 或有效 id 不同于原来的实际 id 时，保存的 id 变为新的有效 id
+    fn set_re(
+        &mut self,
+        real: Option<u32>,
+        effective: Option<u32>,
+        privileged: bool,
+    ) -> Result<()> {
+        if !privileged
+            && (real.is_some_and(|id| id != self.real && id != self.effective)
+                || effective.is_some_and(|id| !self.contains(id)))
+        {
+            return Err(EPERM.with("cannot change real or effective id"));
+        }
+        let old_real = self.real;
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if real.is_some() || effective.is_some_and(|id| id != old_real) {
+            self.saved = self.effective;
+        }
+        Ok(())
+    }
+
+    /// 非特权进程只能将每个 id 改为原来三者之一
+    fn set_res(
+        &mut self,
+        real: Option<u32>,
+        effective: Option<u32>,
+        saved: Option<u32>,
+        privileged: bool,
+    ) -> Result<()> {
+        if !privileged
+            && [real, effective, saved]
+                .into_iter()
+                .flatten()
+                .any(|id| !self.contains(id))
+        {
+            return Err(EPERM.with("cannot change real, effective or saved id"));
+        }
+        self.real = real.unwrap_or(self.real);
+        self.effective = effective.unwrap_or(self.effective);
+        self.saved = saved.unwrap_or(self.saved);
+        Ok(())
+    }
+}
+
+/// 进程的用户和组身份，有效 uid 为0的进程拥有所有权限
+#[derive(Debug, Default, Clone)]
+pub struct Credentials {
+    pub user: Ids,
+    pub group: Ids,
+    /// 附加组
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    #[inline]
+    pub fn euid(&self) -> u32 {
+        self.user.effective
+    }
+
+    #[inline]
+    pub fn egid(&self) -> u32 {
+        self.group.effective
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.euid() == 0
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.egid() == gid || self.groups.contains(&gid)
+    }
+
+    /// 文件所有者和 root 可以修改文件的属性
+    pub fn is_owner(&self, metadata: &FileMetadata) -> bool {
+        self.is_root() || self.euid() == metadata.uid
+    }
+
+    /// 按所有者、组和其他用户选择权限位。root 可以读写所有文件，
+    /// 但只能执行至少有一个执行位的文件
+    pub fn check_access(&self, metadata: &FileMetadata, access: Access) -> Result<()> {
+        let allowed = if self.is_root() {
+            let mut allowed = Access::R | Access::W;
+            allowed.set(Access::X, metadata.mode & 0o111 != 0);
+            allowed
+        } else {
+            let shift = if self.euid() == metadata.uid {
+                6
+            } else if self.in_group(metadata.gid) {
+                3
+            } else {
+                0
+            };
+            Access::from_bits_truncate((metadata.mode >> shift) & 0o7)
+        };
+        if allowed.contains(access) {
+            Ok(())
+        } else {
+            Err(EACCES.with(anyhow!("permission denied: {:?}", access)))
+        }
+    }
+
+    /// 发送者的实际或有效 uid 与目标的实际或保存的 uid 相同时可以发送信号
+    pub fn can_signal(&self, target: &Credentials) -> bool {
+        self.is_root()
+            || [self.user.real, self.user.effective]
+                .into_iter()
+                .any(|uid| uid == target.user.real || uid == target.user.saved)
+    }
+
+    /// 调用者的有效 uid 与目标的实际或有效 uid 相同时可以修改目标的优先级和调度参数
+    pub fn can_renice(&self, target: &Credentials) -> bool {
+        self.is_root()
+            || self.user.effective == target.user.real
+            || self.user.effective == target.user.effective
+    }
+
+    /// 目标的所有 uid 和 gid 都与调用者的实际 id 相同时可以修改目标的资源限制
+    pub fn can_control(&self, target: &Credentials) -> bool {
+        self.is_root() || target.user.all(self.user.real) && target.group.all(self.group.real)
+    }
+
+    /// exec 时按程序的 setuid 和 setgid 位修改有效 id，保存的 id 变为新的有效 id。
+    /// 没有组执行位的 setgid 表示强制锁，不改变身份
+    pub fn exec(&mut self, metadata: &FileMetadata) {
+        if metadata.mode & S_ISUID != 0 {
+            self.user.effective = metadata.uid;
+        }
+        if metadata.mode & S_ISGID != 0 && metadata.mode & S_IXGRP != 0 {
+            self.group.effective = metadata.gid;
+        }
+        self.user.saved = self.user.effective;
+        self.group.saved = self.group.effective;
+    }
+
+    pub fn setuid(&mut self, uid: u32) -> Result<()> {
+        let privileged = self.is_root();
+        self.user.set(uid, privileged)
+    }
+
+    pub fn setgid(&mut self, gid: u32) -> Result<()> {
+        let privileged = self.is_root();
+        self.group.set(gid, privileged)
+    }
+
+    pub fn setreuid(&mut self, ruid: Option<u32>, euid: Option<u32>) -> Result<()> {
+        let privileged = self.is_root();
+        self.user.set_re(ruid, euid, privileged)
+    }
+
+    pub fn setregid(&mut self, rgid: Option<u32>, egid: Option<u32>) -> Result<()> {
+        let privileged = self.is_root();
+        self.group.set_re(rgid, egid, privileged)
+    }
+
+    pub fn setresuid(
+        &mut self,
+        ruid: Option<u32>,
+        euid: Option<u32>,
+        suid: Option<u32>,
+    ) -> Result<()> {
+        let privileged = self.is_root();
+        self.user.set_res(ruid, euid, suid, privileged)
+    }
+
+    pub fn setresgid(
+        &mut self,
+        rgid: Option<u32>,
+        egid: Option<u32>,
+        sgid: Option<u32>,
+    ) -> Result<()> {
+        let privileged = self.is_root();
+        self.group.set_res(rgid, egid, sgid, privileged)
+    }
+
+    /// 只有 root 可以修改附加组
+    pub fn setgroups(&mut self, groups: Vec<u32>) -> Result<()> {
+        if !self.is_root() {
+            return Err(EPERM.with("only root can set supplementary groups"));
+        }
+        self.groups = groups;
+        Ok(())
+    }
+}
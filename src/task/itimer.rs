@@ -0,0 +1,242 @@
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    timer::{
+        add_timer, get_time, ns_to_ticks, ticks_to_ns, TimeSpec, TimeVal, TimerCallback,
+        TimerHandle,
+    },
+    tools::Table,
+};
+
+use super::{process::ProcessControlBlock, signal::SignalFlags};
+
+pub const ITIMER_REAL: usize = 0;
+pub const ITIMER_VIRTUAL: usize = 1;
+pub const ITIMER_PROF: usize = 2;
+
+/// 与 Linux `struct itimerval` 布局一致
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ITimerVal {
+    pub interval: TimeVal,
+    pub value: TimeVal,
+}
+
+impl ITimerVal {
+    /// 由剩余时间和间隔构造，单位为时钟周期
+    pub fn from_ticks((value, interval): (usize, usize)) -> Self {
+        Self {
+            interval: TimeVal::from_ticks(interval),
+            value: TimeVal::from_ticks(value),
+        }
+    }
+
+    pub fn as_ticks(&self) -> (usize, usize) {
+        (
+            ns_to_ticks(self.value.as_ns()),
+            ns_to_ticks(self.interval.as_ns()),
+        )
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.interval.is_valid() && self.value.is_valid()
+    }
+}
+
+/// 与 Linux `struct itimerspec` 布局一致
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ITimerSpec {
+    pub interval: TimeSpec,
+    pub value: TimeSpec,
+}
+
+impl ITimerSpec {
+    pub fn from_ticks((value, interval): (usize, usize)) -> Self {
+        Self {
+            interval: TimeSpec::from_ns(ticks_to_ns(interval)),
+            value: TimeSpec::from_ns(ticks_to_ns(value)),
+        }
+    }
+
+    pub fn as_ticks(&self) -> (usize, usize) {
+        (
+            ns_to_ticks(self.value.as_ns()),
+            ns_to_ticks(self.interval.as_ns()),
+        )
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.interval.is_valid() && self.value.is_valid()
+    }
+}
+
+/// 按时钟计时的定时器，到期时在时钟中断中执行回调
+#[derive(Default)]
+pub struct AlarmTimer {
+    handle: Option<TimerHandle>,
+    deadline: usize,
+    interval: usize,
+}
+
+impl AlarmTimer {
+    /// 在时钟周期 `deadline` 到期，`deadline` 为0时停止定时器。返回之前的剩余时间和间隔
+    pub fn set(
+        &mut self,
+        deadline: usize,
+        interval: usize,
+        callback: TimerCallback,
+    ) -> (usize, usize) {
+        let old = self.get();
+        if let Some(handle) = self.handle.take() {
+            handle.cancel();
+        }
+        self.deadline = deadline;
+        self.interval = interval;
+        if deadline != 0 {
+            self.handle = Some(add_timer(deadline, interval, callback));
+        }
+        old
+    }
+
+    /// 剩余时间和间隔，单位为时钟周期，剩余时间为0表示定时器已停止
+    pub fn get(&self) -> (usize, usize) {
+        if self.handle.is_none() {
+            return (0, self.interval);
+        }
+        let now = get_time();
+        let remaining = if now < self.deadline {
+            self.deadline - now
+        } else if self.interval != 0 {
+            self.interval - (now - self.deadline) % self.interval
+        } else {
+            0
+        };
+        (remaining, self.interval)
+    }
+}
+
+impl Drop for AlarmTimer {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.cancel();
+        }
+    }
+}
+
+/// 按进程 CPU 时间计时的定时器，由时间统计检查是否到期
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CpuTimer {
+    /// 到期时的 CPU 时间，为0表示定时器已停止
+    expires: usize,
+    interval: usize,
+}
+
+impl CpuTimer {
+    /// `now` 为当前的 CPU 时间，返回之前的剩余时间和间隔
+    pub fn set(&mut self, now: usize, value: usize, interval: usize) -> (usize, usize) {
+        let old = self.get(now);
+        self.expires = if value == 0 { 0 } else { now + value };
+        self.interval = interval;
+        old
+    }
+
+    pub fn get(&self, now: usize) -> (usize, usize) {
+        let remaining = if self.expires == 0 {
+            0
+        } else {
+            // 尚未检查到期的定时器至少还剩一个时钟周期
+            self.expires.saturating_sub(now).max(1)
+        };
+        (remaining, self.interval)
+    }
+
+    /// 在 CPU 时间到达 `expires` 时到期，`expires` 为0时停止定时器
+    pub fn arm(&mut self, expires: usize, interval: usize) {
+        self.expires = expires;
+        self.interval = interval;
+    }
+
+    /// CPU 时间推进到 `now` 后是否到期，周期定时器跳过错过的周期后重新开始
+    pub fn expire(&mut self, now: usize) -> bool {
+        if self.expires == 0 || now < self.expires {
+            return false;
+        }
+        self.expires = match self.interval {
+            0 => 0,
+            interval => self.expires + ((now - self.expires) / interval + 1) * interval,
+        };
+        true
+    }
+}
+
+/// `timer_create` 创建的定时器
+pub struct PosixTimer {
+    pub timer: AlarmTimer,
+    /// 到期时发送的信号，为 `None` 时不通知
+    pub signal: Option<SignalFlags>,
+    /// 信号仍未处理时再次到期的次数
+    pub overrun: Arc<AtomicUsize>,
+}
+
+impl PosixTimer {
+    pub fn new(signal: Option<SignalFlags>) -> Self {
+        Self {
+            timer: AlarmTimer::default(),
+            signal,
+            overrun: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// 到期时向进程发送信号的回调
+    pub fn callback(&self, process: Weak<ProcessControlBlock>) -> TimerCallback {
+        let signal = self.signal;
+        let overrun = self.overrun.clone();
+        Arc::new(move || {
+            let (Some(process), Some(signal)) = (process.upgrade(), signal) else {
+                return;
+            };
+            if process.shared.signals.lock().contains(signal) {
+                overrun.fetch_add(1, Ordering::Relaxed);
+            } else {
+                overrun.store(0, Ordering::Relaxed);
+                process.send_signal(signal);
+            }
+        })
+    }
+}
+
+/// 进程的间隔定时器和 POSIX 定时器
+pub struct ProcessTimers {
+    pub real: AlarmTimer,
+    pub virt: CpuTimer,
+    pub prof: CpuTimer,
+    /// `RLIMIT_CPU` 的软限制，超过后每秒发送一次 `SIGXCPU`
+    pub cpu_soft: CpuTimer,
+    /// `RLIMIT_CPU` 的硬限制，超过后发送 `SIGKILL`
+    pub cpu_hard: CpuTimer,
+    pub posix: Table<PosixTimer>,
+}
+
+impl Default for ProcessTimers {
+    fn default() -> Self {
+        Self {
+            real: AlarmTimer::default(),
+            virt: CpuTimer::default(),
+            prof: CpuTimer::default(),
+            cpu_soft: CpuTimer::default(),
+            cpu_hard: CpuTimer::default(),
+            posix: Table::new(),
+        }
+    }
+}
+
+/// 到期时向进程发送 `signal` 的回调
+pub fn signal_callback(process: Weak<ProcessControlBlock>, signal: SignalFlags) -> TimerCallback {
+    Arc::new(move || {
+        if let Some(process) = process.upgrade() {
+            process.send_signal(signal);
+        }
+    })
+}
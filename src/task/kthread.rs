@@ -0,0 +1,127 @@
+use core::arch::naked_asm;
+
+use alloc::{boxed::Box, vec};
+use riscv::register::sstatus;
+
+use crate::ipi::wakeup_all;
+
+use super::{
+    process::KERNEL_PROCESS,
+    processor::{blocking_current, current_killed, exit_current},
+    scheduler::add_task,
+    tcb::Task,
+    tigger::{ThreadsWaiter, Timer},
+};
+
+/// 内核线程的入口
+pub type KernelEntry = Box<dyn FnOnce() + Send + 'static>;
+
+/// 内核线程的句柄，丢弃句柄不影响线程运行。
+///
+/// 内核线程开中断运行，只在安全点被抢占，长时间运行的循环需要调用 `preempt_point`
+pub struct KernelThread {
+    task: Task,
+}
+
+/// 新线程第一次被调度时从这里开始运行，`s0` 为 `KernelEntry` 的指针
+#[naked]
+pub unsafe extern "C" fn kthread_entry() {
+    naked_asm! {r"
+        mv a0, s0
+        j {main}
+        ",
+        main = sym kthread_main,
+        options()
+    }
+}
+
+unsafe extern "C" fn kthread_main(entry: *mut KernelEntry) -> ! {
+    let entry = Box::from_raw(entry);
+    sstatus::set_sie();
+    entry();
+    exit_current(0)
+}
+
+/// 创建运行 `f` 的内核线程并加入调度
+pub fn spawn<F>(f: F) -> KernelThread
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = KERNEL_PROCESS.add_kernel_task(Box::new(f));
+    add_task(task.clone());
+    KernelThread { task }
+}
+
+/// 创建只在硬件线程 `hartid` 上运行的内核线程
+pub fn spawn_on<F>(hartid: usize, f: F) -> KernelThread
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = KERNEL_PROCESS.add_kernel_task(Box::new(f));
+    task.sched.lock().affinity = 1 << hartid;
+    add_task(task.clone());
+    KernelThread { task }
+}
+
+/// 当前内核线程是否被要求停止，循环执行的线程应当检查该值并返回
+pub fn should_stop() -> bool {
+    current_killed()
+}
+
+/// 当前线程阻塞 `ms` 毫秒，被要求停止时提前返回
+pub fn sleep(ms: usize) {
+    blocking_current(Timer::new(ms));
+}
+
+impl KernelThread {
+    pub fn task(&self) -> &Task {
+        &self.task
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.exit_code().is_some()
+    }
+
+    /// 要求线程停止，阻塞中的线程会被唤醒
+    pub fn stop(&self) {
+        self.task.kill();
+        wakeup_all();
+    }
+
+    /// 阻塞当前任务直到线程退出，只能在任务中调用
+    pub fn join(&self) {
+        if !self.is_finished() {
+            blocking_current(ThreadsWaiter::new(vec![self.task.shared.clone()]));
+        }
+    }
+}
+
+/// 在内核线程中检查创建、停止和等待，调度开始后运行
+#[cfg(feature = "debug")]
+pub fn kthread_test() {
+    use crate::tools::ansi::{Color, Colour};
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // `join` 会阻塞当前任务，不能在启动流程中调用
+    spawn(|| {
+        let rounds = Arc::new(AtomicUsize::new(0));
+        let worker = {
+            let rounds = rounds.clone();
+            spawn(move || {
+                while !should_stop() {
+                    rounds.fetch_add(1, Ordering::Relaxed);
+                    sleep(1);
+                }
+            })
+        };
+        sleep(10);
+        assert!(!worker.is_finished());
+        worker.stop();
+        worker.join();
+        assert!(worker.is_finished());
+        assert!(rounds.load(Ordering::Relaxed) > 0);
+        assert!(KERNEL_PROCESS.get_task(worker.task().tid).is_none());
+        println!("[{}] kthread_test", "passed".dye(Color::GreenB));
+    });
+}
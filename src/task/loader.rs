@@ -0,0 +1,228 @@
+use alloc::{string::String, vec::Vec};
+use core::ops::Range;
+
+use anyhow::{anyhow, Result};
+use xmas_elf::{program::Type, ElfFile};
+
+use crate::{
+    config::{INTERP_BASE, PAGE_SIZE, PIE_BASE},
+    fs::inode::{file_metadata, read_file, FileMetadata},
+    mm::memory_set::{ElfLoadInfo, MemorySet},
+    syscall::errno::{ELIBBAD, ELOOP, ENOENT, ENOEXEC},
+};
+
+use super::cred::{Access, Credentials};
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+/// 64位 ELF 程序头的大小
+const PH_ENTRY_SIZE: usize = 56;
+/// Sv39 用户地址空间的上界
+const USER_END: usize = 1 << 38;
+const MAX_INTERP_LEN: usize = 256;
+/// 脚本解释器最多嵌套的层数
+const MAX_SHEBANG_DEPTH: usize = 4;
+
+/// 程序映射在 `INTERP_BASE` 以下，动态链接器映射在 `INTERP_BASE` 以上
+const PROGRAM_RANGE: Range<usize> = PAGE_SIZE..INTERP_BASE;
+const INTERP_RANGE: Range<usize> = INTERP_BASE..USER_END;
+
+fn read_u16(input: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([input[offset], input[offset + 1]])
+}
+
+/// 检查 ELF 文件能否在 `limit` 范围内加载，返回加载偏移
+fn check_elf(elf: &ElfFile, limit: &Range<usize>) -> Result<usize> {
+    let input = elf.input;
+    if input[4] != ELFCLASS64 || input[5] != ELFDATA2LSB {
+        return Err(ENOEXEC.with("not a 64-bit little endian elf"));
+    }
+    let machine = read_u16(input, 18);
+    if machine != EM_RISCV {
+        return Err(ENOEXEC.with(anyhow!("unsupported machine {}", machine)));
+    }
+    let base = match read_u16(input, 16) {
+        ET_EXEC => 0,
+        ET_DYN if limit.start == INTERP_BASE => INTERP_BASE,
+        ET_DYN => PIE_BASE,
+        ty => return Err(ENOEXEC.with(anyhow!("unsupported elf type {}", ty))),
+    };
+    let header = elf.header.pt2;
+    let ph_count = header.ph_count() as usize;
+    let ph_end = (header.ph_offset() as usize).checked_add(ph_count * PH_ENTRY_SIZE);
+    if header.ph_entry_size() as usize != PH_ENTRY_SIZE
+        || ph_end.is_none_or(|end| end > input.len())
+    {
+        return Err(ENOEXEC.with("invalid program header table"));
+    }
+    let entry = base.checked_add(header.entry_point() as usize);
+    let mut loaded: Vec<Range<usize>> = Vec::new();
+    let mut entry_found = false;
+    for ph in elf.program_iter() {
+        let ty = ph
+            .get_type()
+            .map_err(|err| ENOEXEC.with(anyhow!("invalid segment type: {}", err)))?;
+        let (offset, file_size) = (ph.offset() as usize, ph.file_size() as usize);
+        if offset
+            .checked_add(file_size)
+            .is_none_or(|end| end > input.len())
+        {
+            return Err(ENOEXEC.with("segment out of file bounds"));
+        }
+        if ty != Type::Load {
+            continue;
+        }
+        let (vaddr, mem_size, align) = (
+            ph.virtual_addr() as usize,
+            ph.mem_size() as usize,
+            ph.align() as usize,
+        );
+        if file_size > mem_size {
+            return Err(ENOEXEC.with("segment file size exceeds memory size"));
+        }
+        if align > 1 && (!align.is_power_of_two() || vaddr % align != offset % align) {
+            return Err(ENOEXEC.with(anyhow!("misaligned segment at {:#x}", vaddr)));
+        }
+        let start = base.checked_add(vaddr);
+        let end = start.and_then(|start| start.checked_add(mem_size));
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if start >= limit.start && end <= limit.end => (start, end),
+            _ => return Err(ENOEXEC.with(anyhow!("segment at {:#x} out of range", vaddr))),
+        };
+        if mem_size == 0 {
+            continue;
+        }
+        // 不同段映射到同一页时后映射的段会覆盖之前的数据
+        let pages = start / PAGE_SIZE..end.div_ceil(PAGE_SIZE);
+        if loaded
+            .iter()
+            .any(|other| other.start < pages.end && pages.start < other.end)
+        {
+            return Err(ENOEXEC.with(anyhow!("overlapping segment at {:#x}", vaddr)));
+        }
+        loaded.push(pages);
+        entry_found |=
+            ph.flags().is_execute() && entry.is_some_and(|entry| (start..end).contains(&entry));
+    }
+    if loaded.is_empty() {
+        return Err(ENOEXEC.with("no loadable segment"));
+    }
+    if !entry_found {
+        return Err(ENOEXEC.with("entry point outside executable segments"));
+    }
+    Ok(base)
+}
+
+/// `PT_INTERP` 段中以 `\0` 结尾的动态链接器路径，调用前需检查段的范围
+fn interp_path(elf: &ElfFile) -> Result<Option<String>> {
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(Type::Interp) {
+            continue;
+        }
+        let offset = ph.offset() as usize;
+        let data = &elf.input[offset..offset + ph.file_size() as usize];
+        let path = match data.iter().position(|&byte| byte == 0) {
+            Some(len) if len > 0 && len < MAX_INTERP_LEN => &data[..len],
+            _ => return Err(ENOEXEC.with("invalid interpreter path")),
+        };
+        let path = core::str::from_utf8(path).map_err(|err| ENOEXEC.with(anyhow!("{}", err)))?;
+        return Ok(Some(String::from(path)));
+    }
+    Ok(None)
+}
+
+/// 解析 `#!` 行，返回解释器路径和可选的参数
+fn parse_shebang(data: &[u8]) -> Result<(String, Option<String>)> {
+    let line = &data[2..data.len().min(MAX_INTERP_LEN)];
+    let line = match line.iter().position(|&byte| byte == b'\n') {
+        Some(len) => &line[..len],
+        None if data.len() <= MAX_INTERP_LEN => line,
+        None => return Err(ENOEXEC.with("shebang line too long")),
+    };
+    let line = core::str::from_utf8(line)
+        .map_err(|err| ENOEXEC.with(anyhow!("{}", err)))?
+        .trim();
+    // 与 Linux 一致，解释器之后的内容作为一个参数
+    let (interp, arg) = match line.split_once([' ', '\t']) {
+        Some((interp, arg)) => (interp, Some(arg.trim())),
+        None => (line, None),
+    };
+    if interp.is_empty() {
+        return Err(ENOEXEC.with("empty shebang interpreter"));
+    }
+    Ok((
+        String::from(interp),
+        arg.filter(|arg| !arg.is_empty()).map(String::from),
+    ))
+}
+
+/// 读取要运行的程序，`#!` 脚本替换为其解释器，
+/// 参数变为解释器、可选参数、脚本路径和原参数中除第一个外的其余参数
+pub fn read_program(
+    path: &str,
+    mut args: Vec<String>,
+    cred: &Credentials,
+) -> Result<(Vec<u8>, Vec<String>, FileMetadata)> {
+    let mut path = String::from(path);
+    for _ in 0..=MAX_SHEBANG_DEPTH {
+        let (file, metadata) = read_file(&path)
+            .zip(file_metadata(&path))
+            .ok_or_else(|| ENOENT.with(anyhow!("no such file {}", path)))?;
+        // 脚本和解释器都需要执行权限
+        cred.check_access(&metadata, Access::X)?;
+        let data = file.read_all();
+        if !data.starts_with(b"#!") {
+            return Ok((data, args, metadata));
+        }
+        let (interp, arg) = parse_shebang(&data)?;
+        let rest = args.into_iter().skip(1);
+        args = core::iter::once(interp.clone())
+            .chain(arg)
+            .chain(core::iter::once(path))
+            .chain(rest)
+            .collect();
+        path = interp;
+    }
+    Err(ELOOP.with(anyhow!("too many levels of shebang interpreters")))
+}
+
+fn parse_elf(data: &[u8]) -> Result<ElfFile<'_>> {
+    ElfFile::new(data).map_err(|err| ENOEXEC.with(err))
+}
+
+/// 校验并加载程序，动态链接的程序同时加载 `PT_INTERP` 指定的动态链接器，由动态链接器完成重定位
+pub fn load_elf(data: &[u8]) -> Result<(MemorySet, ElfLoadInfo)> {
+    let elf = parse_elf(data)?;
+    let base = check_elf(&elf, &PROGRAM_RANGE)?;
+    let interp_data = match interp_path(&elf)? {
+        Some(path) => {
+            let file = read_file(&path)
+                .ok_or_else(|| ENOENT.with(anyhow!("interpreter {} not found", path)))?;
+            Some(file.read_all())
+        }
+        None => None,
+    };
+    // 动态链接器的错误报告为 ELIBBAD
+    let interp = match &interp_data {
+        Some(data) => {
+            let interp = parse_elf(data).map_err(|err| ELIBBAD.with(err))?;
+            let base = check_elf(&interp, &INTERP_RANGE).map_err(|err| ELIBBAD.with(err))?;
+            if interp_path(&interp)
+                .map_err(|err| ELIBBAD.with(err))?
+                .is_some()
+            {
+                return Err(ELIBBAD.with("interpreter must not have an interpreter"));
+            }
+            Some((interp, base))
+        }
+        None => None,
+    };
+    let (mut memory_set, mut info) = MemorySet::from_elf(&elf, base);
+    if let Some((interp, base)) = interp {
+        memory_set.map_interp(&interp, base, &mut info);
+    }
+    Ok((memory_set, info))
+}
@@ -1,16 +1,29 @@
 use core::arch::naked_asm;
 
-use self::{context::TaskContext, scheduler::add_task};
+use self::{
+    context::TaskContext, cred::Credentials, process::set_initproc, rlimit::ResourceLimits,
+    scheduler::add_task,
+};
 use crate::{fs::inode::open_app, task::scheduler::get_processor};
 
+pub mod auxv;
 pub mod context;
+pub mod cred;
+pub mod itimer;
+pub mod kthread;
+pub mod loader;
+pub mod policy;
+pub mod preempt;
 pub mod process;
 pub mod processor;
+pub mod rlimit;
+pub mod rt;
 pub mod scheduler;
 pub mod signal;
 pub mod tcb;
 pub mod tigger;
 pub mod uid;
+pub mod usage;
 
 #[naked]
 pub unsafe extern "C" fn __switch(
@@ -50,7 +63,16 @@ pub unsafe extern "C" fn __switch(
 
 pub fn add_initproc() {
     // 添加初始程序
-    let (_, initproc) = open_app("initproc", "").unwrap();
+    // initproc 以 root 身份运行
+    let (process, initproc) = open_app(
+        "initproc",
+        &["initproc".into()],
+        &[],
+        &Credentials::default(),
+        &ResourceLimits::default(),
+    )
+    .unwrap();
+    set_initproc(process);
     add_task(initproc);
 }
 
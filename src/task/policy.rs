@@ -0,0 +1,353 @@
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+};
+
+use anyhow::Result;
+
+use crate::{
+    config::{CLOCK_FREQ, NUM_HARTS, TICK_FREQ},
+    syscall::errno::EBUSY,
+    timer::get_time,
+};
+
+use super::{
+    rt::{dl_load, dl_reserve, SchedClass},
+    tcb::Task,
+};
+
+pub const NICE_MIN: i32 = -20;
+pub const NICE_MAX: i32 = 19;
+/// nice 值为0时的权重
+const NICE_0_WEIGHT: usize = 1024;
+/// stride 调度中权重为1时的步长
+const BIG_STRIDE: usize = 1 << 30;
+/// 被唤醒的任务相对于队列最小虚拟时间可获得的补偿
+const WAKEUP_BONUS: usize = CLOCK_FREQ / TICK_FREQ;
+
+/// nice 值 [-20, 19] 对应的权重，相邻 nice 值的 CPU 份额相差约 10%
+const NICE_TO_WEIGHT: [usize; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+pub fn nice_to_weight(nice: i32) -> usize {
+    NICE_TO_WEIGHT[(nice.clamp(NICE_MIN, NICE_MAX) - NICE_MIN) as usize]
+}
+
+/// 所有硬件线程组成的掩码
+pub const ALL_HARTS: usize = (1 << NUM_HARTS) - 1;
+
+/// 任务的调度实体
+#[derive(Debug)]
+pub struct SchedEntity {
+    pub class: SchedClass,
+    /// 允许运行的硬件线程掩码
+    pub affinity: usize,
+    /// 截止期限任务所属的硬件线程，只在该硬件线程上运行
+    pub dl_hart: Option<usize>,
+    /// 上一次运行的硬件线程
+    pub last_hart: Option<usize>,
+    /// nice 值，范围 [-20, 19]
+    pub nice: i32,
+    /// 虚拟时间，stride 调度中为行程，公平调度中为虚拟运行时间
+    pub vtime: usize,
+    /// 截止期限任务当前周期的绝对截止期限
+    pub abs_deadline: usize,
+    /// 截止期限任务当前周期剩余的运行时间
+    pub runtime_left: usize,
+    /// 任务被更高优先级的任务抢占
+    pub preempted: bool,
+}
+
+impl Default for SchedEntity {
+    fn default() -> Self {
+        Self {
+            class: SchedClass::Normal,
+            affinity: ALL_HARTS,
+            dl_hart: None,
+            last_hart: None,
+            nice: 0,
+            vtime: 0,
+            abs_deadline: 0,
+            runtime_left: 0,
+            preempted: false,
+        }
+    }
+}
+
+impl SchedEntity {
+    /// 实际允许运行的硬件线程掩码
+    #[inline]
+    pub fn hart_mask(&self) -> usize {
+        self.dl_hart.map_or(self.affinity, |hartid| 1 << hartid)
+    }
+
+    #[inline]
+    pub fn allowed_on(&self, hartid: usize) -> bool {
+        self.hart_mask() & (1 << hartid) != 0
+    }
+
+    /// 切换调度类。进入截止期限调度类时进行准入控制，优先留在原来的硬件线程，
+    /// 否则选择允许运行的硬件线程中负载最小的一个，并立即开始一个新的周期
+    pub fn set_class(&mut self, class: SchedClass) -> Result<()> {
+        let old = match (self.class, self.dl_hart) {
+            (SchedClass::Deadline(param), Some(hartid)) => Some((hartid, param.bandwidth())),
+            _ => None,
+        };
+        if let SchedClass::Deadline(param) = class {
+            let bw = param.bandwidth();
+            let hartid = match old {
+                Some((hartid, old_bw)) if dl_reserve(hartid, old_bw, bw) => hartid,
+                _ => {
+                    let hartid = (0..NUM_HARTS)
+                        .filter(|&hartid| self.affinity & (1 << hartid) != 0)
+                        .min_by_key(|&hartid| dl_load(hartid))
+                        .filter(|&hartid| dl_reserve(hartid, 0, bw))
+                        .ok_or_else(|| EBUSY.with("deadline bandwidth exceeded"))?;
+                    if let Some((old_hart, old_bw)) = old {
+                        dl_reserve(old_hart, old_bw, 0);
+                    }
+                    hartid
+                }
+            };
+            self.dl_hart = Some(hartid);
+            self.abs_deadline = get_time() + param.deadline;
+            self.runtime_left = param.runtime;
+        } else {
+            if let Some((hartid, old_bw)) = old {
+                dl_reserve(hartid, old_bw, 0);
+            }
+            self.dl_hart = None;
+        }
+        self.class = class;
+        Ok(())
+    }
+
+    /// 子任务继承 nice 值、亲和性和实时调度类，截止期限任务的子任务为普通任务
+    pub fn fork(&self) -> Self {
+        let class = match self.class {
+            SchedClass::Deadline(_) => SchedClass::Normal,
+            class => class,
+        };
+        Self {
+            class,
+            affinity: self.affinity,
+            dl_hart: None,
+            last_hart: None,
+            nice: self.nice,
+            vtime: 0,
+            abs_deadline: 0,
+            runtime_left: 0,
+            preempted: false,
+        }
+    }
+}
+
+impl Drop for SchedEntity {
+    fn drop(&mut self) {
+        // 释放截止期限任务占用的带宽
+        self.set_class(SchedClass::Normal).unwrap();
+    }
+}
+
+/// 处理器就绪队列的调度策略
+pub trait SchedPolicy {
+    fn name(&self) -> &'static str;
+    /// 加入一个就绪任务
+    fn push(&mut self, task: Task);
+    /// 取出下一个要运行的任务
+    fn pop(&mut self) -> Option<Task>;
+    /// 取出第一个满足 `pred` 的任务，用于处理器之间迁移任务
+    fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task>;
+    fn len(&self) -> usize;
+    /// 任务运行了 `ticks` 个时钟周期后被换下
+    fn account(&mut self, _task: &Task, _ticks: usize) {}
+}
+
+/// 轮转调度
+#[derive(Default)]
+pub struct RoundRobin {
+    queue: VecDeque<Task>,
+}
+
+impl SchedPolicy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn push(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn pop(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task> {
+        let idx = self.queue.iter().position(pred)?;
+        self.queue.remove(idx)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// 按虚拟时间排序的就绪队列，虚拟时间最小的任务最先运行
+#[derive(Default)]
+struct VirtualTimeQueue {
+    queue: BTreeMap<(usize, usize), Task>,
+    /// 相同虚拟时间的任务按入队顺序排列
+    seq: usize,
+    /// 队列的最小虚拟时间，单调递增
+    min_vtime: usize,
+}
+
+impl VirtualTimeQueue {
+    fn push(&mut self, task: Task) {
+        let vtime = {
+            let mut sched = task.sched.lock();
+            // 新建或长时间睡眠的任务不能凭借过小的虚拟时间独占处理器
+            sched.vtime = sched.vtime.max(self.min_vtime.saturating_sub(WAKEUP_BONUS));
+            sched.vtime
+        };
+        self.seq += 1;
+        self.queue.insert((vtime, self.seq), task);
+    }
+
+    fn pop(&mut self) -> Option<Task> {
+        let ((vtime, _), task) = self.queue.pop_first()?;
+        self.min_vtime = self.min_vtime.max(vtime);
+        Some(task)
+    }
+
+    fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task> {
+        let key = *self.queue.iter().find(|&(_, task)| pred(task))?.0;
+        self.queue.remove(&key)
+    }
+}
+
+/// stride 调度，每次运行后按优先级前进固定的步长
+#[derive(Default)]
+pub struct Stride {
+    queue: VirtualTimeQueue,
+}
+
+impl SchedPolicy for Stride {
+    fn name(&self) -> &'static str {
+        "stride"
+    }
+
+    fn push(&mut self, task: Task) {
+        self.queue.push(task)
+    }
+
+    fn pop(&mut self) -> Option<Task> {
+        self.queue.pop()
+    }
+
+    fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task> {
+        self.queue.steal(pred)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.queue.len()
+    }
+
+    fn account(&mut self, task: &Task, _ticks: usize) {
+        let mut sched = task.sched.lock();
+        sched.vtime += BIG_STRIDE / nice_to_weight(sched.nice);
+    }
+}
+
+/// 公平调度，按权重折算实际运行时间得到虚拟运行时间
+#[derive(Default)]
+pub struct Fair {
+    queue: VirtualTimeQueue,
+}
+
+impl SchedPolicy for Fair {
+    fn name(&self) -> &'static str {
+        "fair"
+    }
+
+    fn push(&mut self, task: Task) {
+        self.queue.push(task)
+    }
+
+    fn pop(&mut self) -> Option<Task> {
+        self.queue.pop()
+    }
+
+    fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task> {
+        self.queue.steal(pred)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.queue.len()
+    }
+
+    fn account(&mut self, task: &Task, ticks: usize) {
+        let mut sched = task.sched.lock();
+        sched.vtime += ticks * NICE_0_WEIGHT / nice_to_weight(sched.nice);
+    }
+}
+
+/// 根据编译时环境变量 `SCHED` 选择调度策略：rr | stride | fair
+pub fn new_policy() -> Box<dyn SchedPolicy> {
+    match option_env!("SCHED") {
+        Some("stride") => Box::<Stride>::default(),
+        Some("fair") => Box::<Fair>::default(),
+        _ => Box::<RoundRobin>::default(),
+    }
+}
+
+/// 检查 stride 和公平调度按 nice 值分配运行次数，以及新任务的虚拟时间补偿
+#[cfg(feature = "debug")]
+pub fn policy_test() {
+    use super::process::KERNEL_PROCESS;
+    use crate::tools::ansi::{Color, Colour};
+    use alloc::sync::Arc;
+
+    let new_task = |nice| {
+        let task = KERNEL_PROCESS.add_kernel_task(Box::new(|| ()));
+        task.sched.lock().nice = nice;
+        task
+    };
+    let policies: [Box<dyn SchedPolicy>; 2] = [Box::<Stride>::default(), Box::<Fair>::default()];
+    for mut policy in policies {
+        let (high, low) = (new_task(-5), new_task(5));
+        policy.push(high.clone());
+        policy.push(low.clone());
+        let (mut high_runs, mut low_runs) = (0, 0);
+        for round in 0..20 {
+            let task = policy.pop().unwrap();
+            // 虚拟时间相同时按入队顺序运行
+            if round == 0 {
+                assert!(Arc::ptr_eq(&task, &high));
+            }
+            if Arc::ptr_eq(&task, &high) {
+                high_runs += 1;
+            } else {
+                low_runs += 1;
+            }
+            policy.account(&task, WAKEUP_BONUS);
+            policy.push(task);
+        }
+        assert!(low_runs > 0 && high_runs > low_runs * 4);
+        // 新任务得到补偿后最先运行，但虚拟时间不会从0开始
+        let fresh = new_task(0);
+        policy.push(fresh.clone());
+        assert!(fresh.sched.lock().vtime > 0);
+        assert!(Arc::ptr_eq(&policy.pop().unwrap(), &fresh));
+        assert_eq!(policy.len(), 2);
+        for task in [high, low, fresh] {
+            policy.steal(&|other| Arc::ptr_eq(other, &task));
+            KERNEL_PROCESS.remove_task(task.tid);
+        }
+        assert_eq!(policy.len(), 0);
+    }
+    println!("[{}] policy_test", "passed".dye(Color::GreenB));
+}
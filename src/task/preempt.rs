@@ -0,0 +1,107 @@
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use riscv::register::{sie, sip, sstatus};
+use spin::{Mutex, MutexGuard};
+
+use crate::{config::NUM_HARTS, sbi::get_hartid, trap::handle_deferred_interrupts};
+
+/// 每个硬件线程的抢占计数，不为0时安全点不会切换任务。
+/// 任务只在计数为0时切换，因此计数不需要随任务保存
+static PREEMPT_COUNT: [AtomicUsize; NUM_HARTS] = [const { AtomicUsize::new(0) }; NUM_HARTS];
+
+pub fn preempt_count() -> usize {
+    PREEMPT_COUNT[get_hartid()].load(Ordering::Relaxed)
+}
+
+pub fn preempt_disable() {
+    PREEMPT_COUNT[get_hartid()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// 计数减为0时到达安全点，调用者此时不能持有锁
+pub fn preempt_enable() {
+    if PREEMPT_COUNT[get_hartid()].fetch_sub(1, Ordering::Relaxed) == 1 {
+        preempt_point();
+    }
+}
+
+/// 在 `preempt_disable` 和 `preempt_enable` 之间运行 `f`
+pub fn without_preempt<R>(f: impl FnOnce() -> R) -> R {
+    preempt_disable();
+    let result = f();
+    preempt_enable();
+    result
+}
+
+/// 是否有在内核态到达但被推迟处理的中断
+pub fn need_resched() -> bool {
+    let (pending, enabled) = (sip::read(), sie::read());
+    (pending.stimer() && !enabled.stimer())
+        || (pending.ssoft() && !enabled.ssoft())
+        || (pending.sext() && !enabled.sext())
+}
+
+/// 安全点：处理被推迟的中断，可能切换到其它任务。
+/// 调用者不能持有锁，关中断或抢占计数不为0时不做任何事
+pub fn preempt_point() {
+    if preempt_count() != 0 || !sstatus::read().sie() || !need_resched() {
+        return;
+    }
+    unsafe {
+        sstatus::clear_sie();
+        handle_deferred_interrupts(true);
+        sstatus::set_sie();
+    }
+}
+
+/// 持有期间禁止抢占的互斥锁，用于开中断运行时可能在安全点附近持有的锁。
+/// 持有时不能阻塞当前任务
+pub struct PreemptMutex<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+pub struct PreemptMutexGuard<'a, T: ?Sized + 'a> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+}
+
+impl<T> PreemptMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> PreemptMutex<T> {
+    pub fn lock(&self) -> PreemptMutexGuard<'_, T> {
+        preempt_disable();
+        PreemptMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for PreemptMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for PreemptMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for PreemptMutexGuard<'_, T> {
+    /// 先释放锁再恢复抢占，计数归零时到达安全点
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        preempt_enable();
+    }
+}
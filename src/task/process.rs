@@ -1,118 +1,445 @@
 use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
     sync::{Arc, Weak},
     vec::Vec,
 };
+use bitflags::bitflags;
+use core::{
+    mem,
+    sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering},
+};
 
-use spin::{Mutex, RwLock};
-use xmas_elf::ElfFile;
+use anyhow::Result;
+use spin::{Lazy, Mutex, Once, RwLock};
 
 use crate::{
+    config::CLOCK_FREQ,
     fs::{
-        stdio::{Stdin, Stdout},
+        inode::FileMetadata,
+        stdio::{Stdin, Stdout, TTY},
         FileBox,
     },
-    mm::memory_set::MemorySet,
+    ipi::wakeup_all,
+    mm::memory_set::{ElfLoadInfo, MemorySet},
+    sbi::get_hartid,
+    syscall::errno::{EAGAIN, EINVAL, EMFILE},
     tools::Table,
 };
 
 use super::{
-    signal::{Signal, SignalFlags},
-    tcb::{Task, TaskControlBlock},
+    cred::Credentials,
+    itimer::ProcessTimers,
+    kthread::KernelEntry,
+    loader::load_elf,
+    rlimit::{
+        cpu_limit_ticks, ResourceLimits, RLIMIT_CPU, RLIMIT_NOFILE, RLIMIT_NPROC, RLIM_INFINITY,
+    },
+    signal::{DefaultAction, Signal, SignalFlags, SIG_DFL, SIG_IGN},
+    tcb::{SharedStatus, Task, TaskControlBlock},
     uid::{pid_alloc, Pid},
+    usage::{Usage, UsageStat},
 };
 
 pub type Process = Arc<ProcessControlBlock>;
 
+/// 所有存活进程的索引
+static PROCESS_TABLE: Lazy<Mutex<BTreeMap<isize, Weak<ProcessControlBlock>>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+static INITPROC: Once<Process> = Once::new();
+
+/// 内核线程所属的进程
+pub static KERNEL_PROCESS: Lazy<Process> = Lazy::new(ProcessControlBlock::kernel);
+
+pub fn initproc() -> Option<&'static Process> {
+    INITPROC.get()
+}
+
+/// 控制台作为 initproc 所在会话的控制终端
+pub fn set_initproc(process: Process) {
+    TTY.attach(process.get_sid(), process.get_pgid());
+    INITPROC.call_once(|| process);
+}
+
+pub fn find_process(pid: isize) -> Option<Process> {
+    PROCESS_TABLE.lock().get(&pid).and_then(Weak::upgrade)
+}
+
+pub fn all_processes() -> Vec<Process> {
+    PROCESS_TABLE
+        .lock()
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+/// 进程组 `pgid` 中尚未退出的进程
+pub fn process_group(pgid: isize) -> Vec<Process> {
+    all_processes()
+        .into_iter()
+        .filter(|process| process.get_pgid() == pgid && process.exit_code().is_none())
+        .collect()
+}
+
+/// 孤儿进程组中没有进程的父进程属于同一会话的其它进程组，
+/// 没有进程能够让它们继续运行
+pub fn is_orphaned_group(pgid: isize) -> bool {
+    process_group(pgid).iter().all(|process| {
+        let parent = process.inner.read().tree.parent.clone();
+        parent
+            .and_then(|parent| parent.upgrade())
+            .map_or(true, |parent| {
+                parent.get_pgid() == pgid || parent.get_sid() != process.get_sid()
+            })
+    })
+}
+
 pub struct ProcessControlBlock {
     pid: Pid,
-    ustack_base: usize,
+    /// 进程组号
+    pgid: AtomicIsize,
+    /// 会话号
+    sid: AtomicIsize,
+    /// exec 时随新的地址空间改变
+    ustack_base: AtomicUsize,
     pub shared: Arc<ProcessSharedStatus>,
     pub inner: RwLock<ProcessControlBlockInner>,
 }
 
 pub struct ProcessControlBlockInner {
     pub tree: ProcessTree,
-    pub fd_table: Table<FileBox>,
-    pub signal: Signal,
-    pub memory_set: MemorySet,
+    pub fd_table: Arc<RwLock<FdTable>>,
+    /// 环境变量，envp 为空的 exec 和 spawn 的子进程继承该值
+    pub environ: Vec<String>,
+    /// 用户和组身份，fork 时继承
+    pub cred: Credentials,
+    pub rlimits: ResourceLimits,
+    pub signal: Arc<Mutex<Signal>>,
+    pub memory_set: Arc<RwLock<MemorySet>>,
     pub tasks: Table<Task>,
 }
 
+/// 文件描述符表，在 `CLONE_FILES` 创建的进程间共享
+#[derive(Clone)]
+pub struct FdTable {
+    files: Table<FileBox>,
+    /// exec 时需要关闭的文件描述符
+    pub cloexec: BTreeSet<usize>,
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self {
+            files: Table::new(),
+            cloexec: BTreeSet::new(),
+        }
+    }
+}
+
+impl FdTable {
+    fn with_stdio() -> Self {
+        Self {
+            files: Table::<FileBox>::new()
+                .with(Arc::new(Stdin))
+                .with(Arc::new(Stdout))
+                .with(Arc::new(Stdout)),
+            cloexec: BTreeSet::new(),
+        }
+    }
+
+    pub fn get(&self, fd: usize) -> Option<&FileBox> {
+        self.files.get(fd)
+    }
+
+    /// 分配最小的空闲文件描述符，不能超过 `limit`
+    pub fn alloc(&mut self, file: FileBox, limit: usize) -> Result<usize> {
+        let fd = self.files.alloc_id();
+        if fd >= limit {
+            return Err(EMFILE.with("too many open files"));
+        }
+        *self.files.get_entry(fd) = Some(file);
+        Ok(fd)
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Option<FileBox> {
+        self.cloexec.remove(&fd);
+        self.files.remove(fd)
+    }
+
+    pub fn close_on_exec(&mut self) {
+        for fd in mem::take(&mut self.cloexec) {
+            self.files.remove(fd);
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ProcessSharedStatus {
     pub signals: Mutex<SignalFlags>,
     pub state: Mutex<ProcessStatus>,
+    /// 停止或继续运行后尚未被父进程等待的状态
+    pub wait_event: Mutex<Option<i32>>,
+    /// 被信号终止时的等待状态，由最后退出的线程使用
+    pub term_status: Mutex<Option<i32>>,
+    /// 所有线程的资源使用之和
+    pub usage: Usage,
+    /// 已回收的子进程及其后代的资源使用之和
+    pub children_usage: Mutex<UsageStat>,
+    pub timers: Mutex<ProcessTimers>,
+    /// vfork 创建的子进程在 exec 或退出前为 true，父进程等待其变为 false
+    pub vfork: AtomicBool,
+}
+
+/// 正常退出时的等待状态，与 Linux 编码一致
+pub const fn exit_status(code: i32) -> i32 {
+    (code & 0xff) << 8
+}
+
+/// 被信号终止时的等待状态
+pub const fn signal_status(signum: usize, core: bool) -> i32 {
+    signum as i32 | if core { 0x80 } else { 0 }
+}
+
+/// 被信号停止时的等待状态
+pub const fn stop_status(signum: usize) -> i32 {
+    ((signum as i32) << 8) | 0x7f
+}
+
+/// 被 SIGCONT 继续运行时的等待状态
+pub const CONTINUED_STATUS: i32 = 0xffff;
+
+/// `clone` 标志的低8位为子进程退出时发送给父进程的信号，目前总是发送 `SIGCHLD`
+pub const CSIGNAL: u32 = 0xff;
+
+bitflags! {
+    /// 没有工作目录和 System V 信号量，`CLONE_FS` 和 `CLONE_SYSVSEM` 不起作用
+    #[derive(Debug, Clone, Copy)]
+    pub struct CloneFlags: u32 {
+        const CLONE_VM             = 0x0000_0100;
+        const CLONE_FS             = 0x0000_0200;
+        const CLONE_FILES          = 0x0000_0400;
+        const CLONE_SIGHAND        = 0x0000_0800;
+        const CLONE_VFORK          = 0x0000_4000;
+        const CLONE_PARENT         = 0x0000_8000;
+        const CLONE_THREAD         = 0x0001_0000;
+        const CLONE_SYSVSEM        = 0x0004_0000;
+        const CLONE_SETTLS         = 0x0008_0000;
+        const CLONE_PARENT_SETTID  = 0x0010_0000;
+        const CLONE_CHILD_CLEARTID = 0x0020_0000;
+        const CLONE_CHILD_SETTID   = 0x0100_0000;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct WaitOptions: u32 {
+        const WNOHANG    = 1 << 0;
+        const WUNTRACED  = 1 << 1;
+        const WCONTINUED = 1 << 3;
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy)]
 pub enum ProcessStatus {
     #[default]
     Running,
-    Exit(i32),
+    /// 已退出，等待父进程回收，保存等待状态
+    Zombie(i32),
 }
 
 #[derive(Default)]
 pub struct ProcessTree {
     pub parent: Option<Weak<ProcessControlBlock>>,
     pub children: Vec<Process>,
+    /// 被 initproc 收养，退出后由内核直接回收
+    pub orphan: bool,
 }
 
 impl ProcessControlBlockInner {
-    pub fn new(memory_set: MemorySet) -> Self {
+    pub fn new(memory_set: Arc<RwLock<MemorySet>>) -> Self {
         Self {
             tree: ProcessTree::default(),
             memory_set,
-            fd_table: Table::<FileBox>::new()
-                .with(Arc::new(Stdin))
-                .with(Arc::new(Stdout))
-                .with(Arc::new(Stdout)),
+            fd_table: Arc::new(RwLock::new(FdTable::with_stdio())),
+            environ: Vec::new(),
+            cred: Credentials::default(),
+            rlimits: ResourceLimits::default(),
             signal: Default::default(),
             tasks: Table::new(),
         }
     }
+
+    /// 分配最小的空闲文件描述符，不能超过 `RLIMIT_NOFILE`
+    pub fn alloc_fd(&self, file: FileBox) -> Result<usize> {
+        self.fd_table
+            .write()
+            .alloc(file, self.rlimits.cur(RLIMIT_NOFILE))
+    }
 }
 
 impl ProcessControlBlock {
-    pub fn new(memory_set: MemorySet, ustack_base: usize) -> Arc<Self> {
-        Arc::new(Self {
+    pub fn new(memory_set: Arc<RwLock<MemorySet>>, ustack_base: usize) -> Arc<Self> {
+        let process = Arc::new(Self {
             pid: pid_alloc(),
-            ustack_base,
+            pgid: AtomicIsize::new(0),
+            sid: AtomicIsize::new(0),
+            ustack_base: AtomicUsize::new(ustack_base),
             shared: Default::default(),
             inner: RwLock::new(ProcessControlBlockInner::new(memory_set)),
+        });
+        process.set_pgid(process.get_pid());
+        process.set_sid(process.get_pid());
+        PROCESS_TABLE
+            .lock()
+            .insert(process.get_pid(), Arc::downgrade(&process));
+        process
+    }
+
+    /// pid 为0的内核进程，不在进程表中，不会收到信号，也不会退出
+    fn kernel() -> Arc<Self> {
+        let memory_set = Arc::new(RwLock::new(MemorySet::new_bare()));
+        let mut inner = ProcessControlBlockInner::new(memory_set);
+        inner.fd_table = Default::default();
+        Arc::new(Self {
+            pid: Pid { id: 0 },
+            pgid: AtomicIsize::new(0),
+            sid: AtomicIsize::new(0),
+            ustack_base: AtomicUsize::new(0),
+            shared: Default::default(),
+            inner: RwLock::new(inner),
         })
     }
 
-    pub fn add_task(self: &Process, entry: usize, args: &str) -> Task {
+    #[inline]
+    pub fn is_kernel(&self) -> bool {
+        self.get_pid() == 0
+    }
+
+    /// 在内核进程中创建运行 `entry` 的内核线程
+    pub fn add_kernel_task(self: &Process, entry: KernelEntry) -> Task {
+        let memory_set = self.inner.read().memory_set.clone();
+        let mut inner = self.inner.write();
+        let tid = inner.tasks.alloc_id();
+        let task = TaskControlBlock::new_kernel(self, &memory_set, tid, entry);
+        task.local.borrow_mut().thread_id = Some(pid_alloc());
+        *inner.tasks.get_entry(tid) = Some(task.clone());
+        task
+    }
+
+    pub fn add_task(self: &Process, info: &ElfLoadInfo, args: &[String]) -> Task {
         let tid = self.inner.write().tasks.alloc_id();
-        let task = TaskControlBlock::new(self, tid, entry, self.ustack_base, args);
+        let ustack_base = self.ustack_base.load(Ordering::Relaxed);
+        let envs = self.inner.read().environ.clone();
+        let task = TaskControlBlock::new(self, tid, ustack_base, info, args, &envs);
         *self.inner.write().tasks.get_entry(tid) = Some(task.clone());
+        self.update_rss();
         task
     }
 
-    pub fn from_elf(elf: ElfFile, args: &str) -> (Arc<Self>, Task) {
-        let (memory_set, entry, ustack_base) = MemorySet::from_elf(&elf);
+    /// 资源限制在分配用户栈之前设置
+    pub fn from_elf(
+        data: &[u8],
+        args: &[String],
+        envs: &[String],
+        rlimits: &ResourceLimits,
+    ) -> Result<(Arc<Self>, Task)> {
+        let (memory_set, info) = load_elf(data)?;
         // let usp = push_args(&memory_set, ustack_base, args);
-        let result = Self::new(memory_set, ustack_base);
-        let task = result.add_task(entry, args);
-        (result, task)
-    }
-
-    /// 注意：当前实现在多线程下是不正确的，会出现不可预知的问题
-    pub unsafe fn fork(self: &Process) -> Arc<Self> {
-        let memory_set = MemorySet::from_existed(&self.inner.read().memory_set);
-        let new_process = Self::new(memory_set, self.ustack_base);
-        let fd_table = &self.inner.read().fd_table.clone();
-        new_process.inner.write().fd_table = fd_table.clone();
-        let tasks: Table<Task> = self
-            .inner
-            .read()
-            .tasks
-            .iter()
-            .map(|task| task.as_ref().map(|x| x.fork(&new_process)))
-            .collect();
-        new_process.set_parent(self);
-        new_process.inner.write().tasks = tasks;
-        new_process
+        let result = Self::new(Arc::new(RwLock::new(memory_set)), info.ustack_base);
+        {
+            let mut inner = result.inner.write();
+            inner.environ = envs.to_vec();
+            inner.rlimits = rlimits.clone();
+        }
+        result.update_cpu_limit();
+        let task = result.add_task(&info, args);
+        Ok((result, task))
+    }
+
+    /// 按 `flags` 复制进程，子进程只包含调用线程 `task` 的副本
+    pub fn clone_process(
+        self: &Process,
+        task: &Task,
+        flags: CloneFlags,
+        stack: usize,
+        tls: usize,
+    ) -> Result<(Process, Task)> {
+        // `CLONE_PARENT` 创建的子进程与调用者是兄弟进程
+        let parent = if flags.contains(CloneFlags::CLONE_PARENT) {
+            self.inner
+                .read()
+                .tree
+                .parent
+                .as_ref()
+                .and_then(|parent| parent.upgrade())
+                .ok_or_else(|| EINVAL.with("CLONE_PARENT without parent"))?
+        } else {
+            self.clone()
+        };
+        let memory_set = if flags.contains(CloneFlags::CLONE_VM) {
+            self.inner.read().memory_set.clone()
+        } else {
+            // 复制期间不持有进程的锁，以便在安全点被抢占
+            let space = self.inner.read().memory_set.clone();
+            Arc::new(RwLock::new(MemorySet::from_existed(&space)))
+        };
+        let process = Self::new(memory_set.clone(), self.ustack_base.load(Ordering::Relaxed));
+        let child_task = {
+            let inner = self.inner.read();
+            let mut child = process.inner.write();
+            child.fd_table = if flags.contains(CloneFlags::CLONE_FILES) {
+                inner.fd_table.clone()
+            } else {
+                Arc::new(RwLock::new(inner.fd_table.read().clone()))
+            };
+            child.signal = if flags.contains(CloneFlags::CLONE_SIGHAND) {
+                inner.signal.clone()
+            } else {
+                Arc::new(Mutex::new(inner.signal.lock().clone()))
+            };
+            child.environ = inner.environ.clone();
+            child.cred = inner.cred.clone();
+            child.rlimits = inner.rlimits.clone();
+            let tid = child.tasks.alloc_id();
+            let child_task = task.clone_task(&process, &memory_set, tid, flags, stack, tls);
+            *child.tasks.get_entry(tid) = Some(child_task.clone());
+            child_task
+        };
+        process.update_cpu_limit();
+        unsafe { process.set_parent(&parent) };
+        process.set_pgid(self.get_pgid());
+        process.set_sid(self.get_sid());
+        process
+            .shared
+            .vfork
+            .store(flags.contains(CloneFlags::CLONE_VFORK), Ordering::Release);
+        process.update_rss();
+        Ok((process, child_task))
+    }
+
+    /// 在当前进程中创建调用线程 `task` 的副本
+    pub fn clone_thread(
+        self: &Process,
+        task: &Task,
+        flags: CloneFlags,
+        stack: usize,
+        tls: usize,
+    ) -> Task {
+        let memory_set = self.inner.read().memory_set.clone();
+        let mut inner = self.inner.write();
+        let tid = inner.tasks.alloc_id();
+        let child_task = task.clone_task(self, &memory_set, tid, flags, stack, tls);
+        child_task.local.borrow_mut().thread_id = Some(pid_alloc());
+        *inner.tasks.get_entry(tid) = Some(child_task.clone());
+        child_task
+    }
+
+    /// vfork 创建的子进程 exec 或退出时唤醒等待的父进程
+    fn release_vfork(&self) {
+        if self.shared.vfork.swap(false, Ordering::AcqRel) {
+            wakeup_all();
+        }
     }
 
     #[inline]
@@ -120,37 +447,347 @@ impl ProcessControlBlock {
         self.pid.id
     }
 
-    /// 获取其它线程的任务是不安全的，因为 `TaskControlBlock` 不是线程安全的
-    pub unsafe fn find_child(&self, pid: isize) -> Option<(usize, Process)> {
-        self.inner
-            .read()
-            .tree
-            .children
-            .iter()
-            .enumerate()
-            .find_map(|(idx, process)| {
-                if process.get_pid() == pid {
-                    Some((idx, process.clone()))
-                } else {
-                    None
-                }
-            })
-    }
-    /// 父子任务必须是同一个线程的
+    /// 父子任务必须是同一个线程的，子进程继承父进程的进程组和会话
     pub unsafe fn set_parent(self: &Process, parent: &Process) {
         parent.inner.write().tree.children.push(self.clone());
         self.inner.write().tree.parent = Some(Arc::downgrade(parent));
+        self.set_pgid(parent.get_pgid());
+        self.set_sid(parent.get_sid());
+    }
+
+    #[inline]
+    pub fn get_pgid(&self) -> isize {
+        self.pgid.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pgid(&self, pgid: isize) {
+        self.pgid.store(pgid, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn get_sid(&self) -> isize {
+        self.sid.load(Ordering::Relaxed)
+    }
+
+    pub fn set_sid(&self, sid: isize) {
+        self.sid.store(sid, Ordering::Relaxed);
+    }
+
+    /// 会话首进程
+    pub fn is_session_leader(&self) -> bool {
+        self.get_sid() == self.get_pid()
+    }
+
+    /// 停止或继续运行时通知父进程
+    pub fn report_event(&self, status: i32) {
+        *self.shared.wait_event.lock() = Some(status);
+        let parent = self.inner.read().tree.parent.clone();
+        if let Some(parent) = parent.and_then(|parent| parent.upgrade()) {
+            parent.send_signal(SignalFlags::SIGCHLD);
+        }
+        wakeup_all();
+    }
+
+    /// 取出可以被父进程等待的状态，第二个值表示进程已退出需要回收
+    pub fn take_wait_status(&self, options: WaitOptions) -> Option<(i32, bool)> {
+        if let Some(status) = self.exit_code() {
+            return Some((status, true));
+        }
+        let mut event = self.shared.wait_event.lock();
+        match *event {
+            Some(CONTINUED_STATUS) if options.contains(WaitOptions::WCONTINUED) => {}
+            Some(status)
+                if status != CONTINUED_STATUS && options.contains(WaitOptions::WUNTRACED) => {}
+            _ => return None,
+        }
+        event.take().map(|status| (status, false))
     }
     pub fn exit_code(&self) -> Option<i32> {
-        if let ProcessStatus::Exit(code) = *self.shared.state.lock() {
+        if let ProcessStatus::Zombie(code) = *self.shared.state.lock() {
             Some(code)
         } else {
             None
         }
     }
-    pub fn exit(&self, code: i32) {
+    /// `status` 为父进程等待时得到的状态
+    pub fn exit(&self, status: i32) {
+        self.release_vfork();
+        let children = mem::take(&mut self.inner.write().tree.children);
+        self.reparent(children);
+        if self.is_session_leader() {
+            TTY.hangup(self.get_sid());
+        }
         self.clear_res();
-        *self.shared.state.lock() = ProcessStatus::Exit(code);
+        *self.shared.state.lock() = ProcessStatus::Zombie(status);
+        // 必须在进入僵尸状态后读取父进程，与 `reparent` 配合保证被收养的进程一定被回收
+        let (parent, orphan) = {
+            let inner = self.inner.read();
+            (inner.tree.parent.clone(), inner.tree.orphan)
+        };
+        if let Some(parent) = parent.and_then(|parent| parent.upgrade()) {
+            if orphan {
+                parent.reap_child(self);
+            } else {
+                parent.send_signal(SignalFlags::SIGCHLD);
+            }
+        }
+        // 等待该进程的任务可能阻塞在其它处理器上
+        wakeup_all();
+    }
+
+    /// 将子进程交给 initproc 收养，已经退出的子进程直接回收。
+    /// 检查和加入在 initproc 的锁内完成，与 `reap_child` 配合保证每个子进程恰好被回收一次
+    fn reparent(&self, children: Vec<Process>) {
+        let Some(init) = initproc().filter(|init| init.get_pid() != self.get_pid()) else {
+            return;
+        };
+        for child in children {
+            {
+                let mut child_inner = child.inner.write();
+                child_inner.tree.parent = Some(Arc::downgrade(init));
+                child_inner.tree.orphan = true;
+            }
+            let mut init_inner = init.inner.write();
+            if child.exit_code().is_none() {
+                init_inner.tree.children.push(child);
+            } else {
+                drop(init_inner);
+                init.collect_usage(&child);
+            }
+        }
+    }
+
+    /// 从子进程列表中移除并回收子进程，只有成功移除的一方统计资源使用。
+    /// 被收养的子进程尚未加入列表时由 `reparent` 回收
+    pub fn reap_child(&self, child: &ProcessControlBlock) {
+        let found = {
+            let mut inner = self.inner.write();
+            let children = &mut inner.tree.children;
+            let len = children.len();
+            children.retain(|process| process.get_pid() != child.get_pid());
+            children.len() != len
+        };
+        if found {
+            self.collect_usage(child);
+        }
+    }
+
+    /// 进程自身及已回收后代的资源使用之和
+    pub fn total_usage(&self) -> UsageStat {
+        let mut usage = self.shared.usage.stat();
+        usage.merge(&self.shared.children_usage.lock());
+        usage
+    }
+
+    /// 回收子进程时累加其资源使用
+    pub fn collect_usage(&self, child: &ProcessControlBlock) {
+        let usage = child.total_usage();
+        self.shared.children_usage.lock().merge(&usage);
+    }
+
+    /// 用当前占用的物理页面更新最大驻留内存
+    pub fn update_rss(&self) {
+        let size = self.inner.read().memory_set.read().resident_size();
+        self.shared.usage.update_rss(size);
+    }
+
+    /// 向进程发送信号，不会被处理的信号直接丢弃，未被屏蔽的致命信号立即终止进程
+    pub fn send_signal(&self, flag: SignalFlags) {
+        if self.exit_code().is_some() {
+            return;
+        }
+        if flag == SignalFlags::SIGKILL {
+            self.terminate(signal_status(flag.signum(), false));
+            return;
+        }
+        let inner = self.inner.read();
+        let handler = inner.signal.lock().actions[flag.signum()].handler;
+        let stops = SignalFlags::SIGSTOP
+            | SignalFlags::SIGTSTP
+            | SignalFlags::SIGTTIN
+            | SignalFlags::SIGTTOU;
+        match flag.default_action() {
+            DefaultAction::Continue => {
+                // 无论如何处理 `SIGCONT`，停止的线程都会继续运行
+                self.shared.signals.lock().remove(stops);
+                for task in inner.tasks.iter_elem() {
+                    let mut signals = task.shared.signals.lock();
+                    signals.remove(stops);
+                    signals.insert(flag);
+                }
+                wakeup_all();
+            }
+            DefaultAction::Stop if handler == SIG_DFL || flag.is_uncatchable() => {
+                // 孤儿进程组停止后无法被继续运行，丢弃终端产生的停止信号
+                if flag != SignalFlags::SIGSTOP && is_orphaned_group(self.get_pgid()) {
+                    return;
+                }
+                // 所有线程都需要停止
+                self.shared.signals.lock().remove(SignalFlags::SIGCONT);
+                for task in inner.tasks.iter_elem() {
+                    task.shared.signals.lock().insert(flag);
+                }
+                // 阻塞的线程被唤醒后停止
+                wakeup_all();
+                return;
+            }
+            _ => (),
+        }
+        let ignored = match handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(
+                flag.default_action(),
+                DefaultAction::Ignore | DefaultAction::Continue
+            ),
+            _ => false,
+        };
+        if ignored {
+            return;
+        }
+        // 只要有一个线程没有屏蔽致命信号，进程就会被终止
+        let fatal = handler == SIG_DFL
+            && inner
+                .tasks
+                .iter_elem()
+                .any(|task| !task.shared.sigmask.lock().contains(flag));
+        drop(inner);
+        if fatal {
+            let core = flag.default_action() == DefaultAction::CoreDump;
+            self.terminate(signal_status(flag.signum(), core));
+        } else {
+            self.shared.signals.lock().insert(flag);
+            // 唤醒可被信号打断的阻塞线程
+            wakeup_all();
+        }
+    }
+
+    /// CPU 时间增加后检查 `ITIMER_VIRTUAL`、`ITIMER_PROF` 和 `RLIMIT_CPU` 是否到期
+    pub fn check_cpu_timers(&self) {
+        let (utime, stime) = self.shared.usage.times();
+        let (virt, prof, soft, hard) = {
+            let mut timers = self.shared.timers.lock();
+            (
+                timers.virt.expire(utime),
+                timers.prof.expire(utime + stime),
+                timers.cpu_soft.expire(utime + stime),
+                timers.cpu_hard.expire(utime + stime),
+            )
+        };
+        if virt {
+            self.send_signal(SignalFlags::SIGVTALRM);
+        }
+        if prof {
+            self.send_signal(SignalFlags::SIGPROF);
+        }
+        if hard {
+            self.send_signal(SignalFlags::SIGKILL);
+        } else if soft {
+            self.send_signal(SignalFlags::SIGXCPU);
+        }
+    }
+
+    /// 按 `RLIMIT_CPU` 设置 CPU 时间到达软限制和硬限制时的定时器
+    pub fn update_cpu_limit(&self) {
+        let limit = self.inner.read().rlimits.get(RLIMIT_CPU).unwrap();
+        let mut timers = self.shared.timers.lock();
+        timers.cpu_soft.arm(cpu_limit_ticks(limit.cur), CLOCK_FREQ);
+        timers.cpu_hard.arm(cpu_limit_ticks(limit.max), 0);
+    }
+
+    /// 同一实际用户的进程数达到 `RLIMIT_NPROC` 时不能再创建进程，root 不受限制
+    pub fn check_nproc(&self) -> Result<()> {
+        let (uid, limit) = {
+            let inner = self.inner.read();
+            if inner.cred.is_root() {
+                return Ok(());
+            }
+            (inner.cred.user.real, inner.rlimits.cur(RLIMIT_NPROC))
+        };
+        if limit == RLIM_INFINITY {
+            return Ok(());
+        }
+        let count = all_processes()
+            .iter()
+            .filter(|process| {
+                process.exit_code().is_none() && process.inner.read().cred.user.real == uid
+            })
+            .count();
+        if count >= limit {
+            return Err(EAGAIN.with("too many processes"));
+        }
+        Ok(())
+    }
+
+    /// 以等待状态 `status` 终止进程的所有线程
+    pub fn terminate(&self, status: i32) {
+        self.shared.term_status.lock().get_or_insert(status);
+        for task in self.inner.read().tasks.iter_elem() {
+            task.kill();
+        }
+        // 让其它处理器上的线程陷入内核并退出
+        wakeup_all();
+    }
+
+    /// 终止除 `tid` 外的所有线程，返回它们的共享状态用于等待退出
+    pub fn kill_others(&self, tid: usize) -> Vec<Arc<SharedStatus>> {
+        let others: Vec<_> = self
+            .inner
+            .read()
+            .tasks
+            .iter_elem()
+            .filter(|task| task.tid != tid)
+            .map(|task| {
+                task.kill();
+                task.shared.clone()
+            })
+            .collect();
+        if !others.is_empty() {
+            // 让其它处理器上的线程陷入内核并退出
+            wakeup_all();
+        }
+        others
+    }
+
+    /// 用新的程序替换进程映像，调用者必须是进程中唯一的线程
+    pub fn exec(
+        &self,
+        task: &Task,
+        mut memory_set: MemorySet,
+        info: ElfLoadInfo,
+        args: &[String],
+        envs: Vec<String>,
+        metadata: &FileMetadata,
+    ) {
+        task.clear_child_tid();
+        // 与 Linux 一致，exec 后调用线程的 tid 与进程的 pid 相同
+        task.local.borrow_mut().thread_id = None;
+        let old_slot = task.exec(&mut memory_set, &info, args, &envs);
+        memory_set.set_active(get_hartid(), true);
+        let mut inner = self.inner.write();
+        let old = mem::replace(&mut inner.memory_set, Arc::new(RwLock::new(memory_set)));
+        self.ustack_base.store(info.ustack_base, Ordering::Relaxed);
+        inner.environ = envs;
+        inner.cred.exec(metadata);
+        // 信号处理函数在新的地址空间中无效，屏蔽字保持不变。
+        // 信号处理函数和文件描述符表不再与其它进程共享
+        let mut signal = inner.signal.lock().clone();
+        signal.actions.reset_handlers();
+        inner.signal = Arc::new(Mutex::new(signal));
+        let mut fd_table = inner.fd_table.read().clone();
+        fd_table.close_on_exec();
+        inner.fd_table = Arc::new(RwLock::new(fd_table));
+        drop(inner);
+        // 原地址空间可能仍被 vfork 的父进程使用
+        {
+            let mut old = old.write();
+            old.set_active(get_hartid(), false);
+            old.trap_slots.remove(old_slot);
+        }
+        drop(old);
+        self.update_rss();
+        self.release_vfork();
+        // POSIX 定时器在 exec 后被删除，间隔定时器保留
+        self.shared.timers.lock().posix.clear();
     }
 
     pub fn get_task(&self, tid: usize) -> Option<Task> {
@@ -161,11 +798,28 @@ impl ProcessControlBlock {
         self.inner.write().tasks.remove(tid);
     }
 
+    /// 设置所有线程的 nice 值
+    pub fn set_nice(&self, nice: i32) {
+        for task in self.inner.read().tasks.iter_elem() {
+            task.sched.lock().nice = nice;
+        }
+    }
+
+    pub fn get_nice(&self) -> Option<i32> {
+        let inner = self.inner.read();
+        let task = inner.tasks.iter_elem().next()?;
+        let nice = task.sched.lock().nice;
+        Some(nice)
+    }
+
     pub fn clear_res(&self) {
         let mut inner = self.inner.write();
-        inner.fd_table.clear();
+        // 文件描述符表可能与其它进程共享
+        inner.fd_table = Default::default();
         inner.tree.children.clear();
         inner.tasks.clear();
+        drop(inner);
+        *self.shared.timers.lock() = ProcessTimers::default();
     }
 
     // pub fn alloc_tid(&self) -> (usize, &mut Option<Task>) {
@@ -199,3 +853,9 @@ impl ProcessControlBlock {
     //     }
     // }
 }
+
+impl Drop for ProcessControlBlock {
+    fn drop(&mut self) {
+        PROCESS_TABLE.lock().remove(&self.get_pid());
+    }
+}
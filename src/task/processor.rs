@@ -1,30 +1,49 @@
 use core::{
     cell::{Cell, RefCell},
-    hint,
     sync::atomic::Ordering,
     task::Poll,
 };
 
 use alloc::{boxed::Box, collections::VecDeque};
+use riscv::register::{sip, sstatus};
 
-use spin::{Lazy, Mutex};
+use spin::Mutex;
 
 use crate::{
-    config::{CLOCK_FREQ, TICK_FREQ},
-    task::{__switch, context::TaskContext, scheduler::get_processor},
-    timer::get_time,
+    ipi::handle_pending_ipi,
+    sbi::halt,
+    task::{
+        __switch,
+        context::TaskContext,
+        scheduler::{get_processor, GLOBAL_SCHEDULER},
+    },
+    timer::{get_time, handle_timer_interrupt, set_tick},
+    trap::handle_deferred_interrupts,
 };
 
 use super::{
+    policy::new_policy,
+    preempt::preempt_count,
+    rt::{ClassQueue, SchedClass, RR_TIMESLICE},
     tcb::{Task, TaskStatus, TASK_SEND_LOCK, TASK_SEND_UNLOCK},
-    tigger::{Future, FutureBox},
+    tigger::{Future, FutureBox, Timer},
 };
 
 pub struct Processor {
+    hartid: usize,
     current: Cell<Option<Task>>,
-    poll_time: Cell<usize>,
+    /// 当前任务开始运行的时间
+    switch_time: Cell<usize>,
+    /// 等待条件可能已经改变，需要轮询等待队列
+    wait_notified: Cell<bool>,
     queue: TaskQueue,
+    /// 距离下一次负载均衡的调度时钟数
+    balance_ticks: Cell<usize>,
     switch_trampoline: RefCell<TaskContext>,
+    /// 任务退出时保存无用的上下文
+    hole: RefCell<TaskContext>,
+    /// 不允许在当前处理器上运行、等待上下文保存完毕后迁移的任务
+    migrating: Cell<Option<Task>>,
 }
 
 pub struct BlockedTask {
@@ -36,7 +55,12 @@ impl BlockedTask {
     pub fn new(task: Task, tigger: FutureBox) -> Self {
         Self { task, tigger }
     }
+    /// 被终止的任务立即唤醒，以便退出
     pub fn poll(&self) -> Option<Task> {
+        if self.task.is_killed() {
+            self.task.set_state(TaskStatus::Ready);
+            return Some(self.task.clone());
+        }
         match self.tigger.poll() {
             Poll::Ready(_) => {
                 self.task.set_state(TaskStatus::Ready);
@@ -48,18 +72,22 @@ impl BlockedTask {
 }
 
 pub struct TaskQueue {
-    pub queue: Mutex<VecDeque<Task>>,
+    pub queue: Mutex<ClassQueue>,
     pub wait_queue: Mutex<VecDeque<BlockedTask>>,
 }
 
 impl Processor {
     pub fn new(hartid: usize) -> Self {
         Self {
-            // hartid,
+            hartid,
             current: Cell::new(None),
-            poll_time: Cell::new(get_time()),
+            switch_time: Cell::new(0),
+            wait_notified: Cell::new(false),
             queue: TaskQueue::new(),
+            balance_ticks: Cell::new(BALANCE_INTERVAL),
             switch_trampoline: RefCell::new(TaskContext::switch_trampoline(hartid)),
+            hole: RefCell::new(TaskContext::default()),
+            migrating: Cell::new(None),
             // task_manager: task_maneger,
         }
     }
@@ -69,12 +97,36 @@ impl Processor {
         // if let Some(task) = &new {
         //     task.set_state(TaskStatus::Running);
         // }
-        self.current.set(new);
+        if let Some(task) = &new {
+            task.resume_time();
+            // 任务可能从其它处理器迁移而来，陷入内核时需要恢复正确的 hartid
+            unsafe { task.trap_context().hartid = self.hartid };
+            unsafe { task.space().set_active(self.hartid, true) };
+            let last_hart = task.sched.lock().last_hart.replace(self.hartid);
+            if last_hart.is_some_and(|hartid| hartid != self.hartid) {
+                task.account_migration();
+            }
+        }
+        self.switch_time.set(get_time());
+        if let Some(old) = self.current.replace(new) {
+            unsafe { old.space().set_active(self.hartid, false) };
+        }
+        self.update_tick();
+    }
+
+    /// 取出当前任务，该任务的地址空间不再在此处理器上使用
+    fn take_current(&self) -> Option<Task> {
+        let task = self.current.take()?;
+        unsafe { task.space().set_active(self.hartid, false) };
+        Some(task)
     }
 
     pub fn ready_task_num(&self) -> usize {
         self.queue.ready_task_num()
     }
+    pub fn policy_name(&self) -> &'static str {
+        self.queue.queue.lock().name()
+    }
     pub fn add_task(&self, task: Task) {
         // match *task.state.lock() {
         //     TaskStatus::Ready => self.queue.push_ready(task),
@@ -84,44 +136,113 @@ impl Processor {
         assert!(task.is_ready());
         self.queue.push_task(task, None)
     }
-    pub fn fetch_task(&self) -> Option<Task> {
-        self.queue
-            .queue
-            .lock()
-            .iter()
-            .find(|task| task.send_lock.load(Ordering::Acquire) == TASK_SEND_UNLOCK)
-            .cloned()
+    /// 取出一个上下文已保存完毕且允许在 `hartid` 上运行的就绪任务，用于迁移到其它处理器
+    pub fn fetch_task(&self, hartid: usize) -> Option<Task> {
+        self.queue.queue.lock().steal(&|task| {
+            task.send_lock.load(Ordering::Acquire) == TASK_SEND_UNLOCK
+                && task.sched.lock().allowed_on(hartid)
+        })
     }
 }
 
-const POLL_TIME_INTERVAL: usize = CLOCK_FREQ / (2 * TICK_FREQ);
+/// 负载均衡的间隔（调度时钟数）
+const BALANCE_INTERVAL: usize = 4;
 
 pub unsafe fn switch_trampoline() {
     let processor = get_processor();
     processor.set_current(None);
+    // 此时被迁移任务的上下文已经保存完毕
+    if let Some(task) = processor.migrating.take() {
+        GLOBAL_SCHEDULER.add_task(task);
+    }
     processor.entrap_task()
 }
 
 impl Processor {
+    /// 让处理器在下一次调度时轮询等待队列。
+    /// 等待队列只在收到通知时轮询，改变等待条件后必须通过定时器或 `wakeup_all` 通知
+    pub fn notify_wait(&self) {
+        self.wait_notified.set(true);
+    }
     fn try_poll_wait(&self) {
-        let time = get_time();
-        if time > self.poll_time.get() {
-            self.poll_time.set(time + POLL_TIME_INTERVAL);
-            self.queue.poll_all_wait();
+        if self.wait_notified.replace(false) {
+            self.queue.poll_all_wait(self.hartid);
+        }
+    }
+    /// 只有存在其它就绪任务或当前任务需要运行时间限制时才需要调度时钟
+    pub fn update_tick(&self) {
+        let deadline = unsafe { (*self.current.as_ptr()).as_ref() }
+            .is_some_and(|task| matches!(task.sched.lock().class, SchedClass::Deadline(_)));
+        set_tick(deadline || self.queue.ready_task_num() > 0);
+    }
+    /// 关闭调度时钟并等待中断，只有等待任务自己的定时器或处理器间中断能唤醒空闲的处理器。
+    /// 由于调度时中断关闭，需要手动处理时钟中断
+    fn idle(&self) {
+        set_tick(false);
+        halt();
+        if sip::read().stimer() {
+            handle_timer_interrupt();
+        }
+    }
+    /// 时钟中断时决定是否切换当前任务，`tick` 表示是否到达调度时钟
+    pub fn on_tick(&self, tick: bool) {
+        self.try_poll_wait();
+        self.update_tick();
+        if tick {
+            self.try_balance();
+        }
+        let task = current_task();
+        if self.queue.queue.lock().should_preempt(&task) {
+            task.sched.lock().preempted = true;
+            drop(task);
+            yield_();
+            return;
+        }
+        let ran = get_time() - self.switch_time.get();
+        let (class, abs_deadline, runtime_left) = {
+            let sched = task.sched.lock();
+            (sched.class, sched.abs_deadline, sched.runtime_left)
+        };
+        drop(task);
+        match class {
+            SchedClass::Normal if tick => yield_(),
+            SchedClass::RoundRobin(_) if ran >= RR_TIMESLICE => yield_(),
+            // 运行时间耗尽的截止期限任务被限制运行直到下一个周期
+            SchedClass::Deadline(param) if ran >= runtime_left => {
+                let next_period = abs_deadline - param.deadline + param.period;
+                blocking_current(Timer::until(next_period));
+            }
+            _ => {}
+        }
+    }
+    fn try_balance(&self) {
+        let ticks = self.balance_ticks.get() - 1;
+        if ticks == 0 {
+            self.balance_ticks.set(BALANCE_INTERVAL);
+            GLOBAL_SCHEDULER.balance(self.hartid);
+        } else {
+            self.balance_ticks.set(ticks);
         }
     }
     fn get_ready_task_spin(&self) -> Task {
         loop {
+            handle_pending_ipi();
             self.try_poll_wait();
             if let Some(task) = self.queue.pop_ready() {
                 break task;
             }
-            for _ in 0..32 {
-                hint::spin_loop();
+            // 空闲时从其它处理器窃取任务
+            if let Some(task) = GLOBAL_SCHEDULER.fetch_task(self.hartid, 1) {
+                break task;
             }
+            self.idle();
         }
     }
     pub fn entrap_task(&self) -> ! {
+        unsafe {
+            sstatus::clear_sie();
+            handle_deferred_interrupts(false);
+        }
         let next: *mut TaskContext;
         if unsafe { (*self.current.as_ptr()).is_none() } {
             let task = self.get_ready_task_spin();
@@ -131,21 +252,49 @@ impl Processor {
             // 当无法找到下一个任务时切换到初始化栈 ,避免在当前栈退出任务
             next = self.switch_trampoline.as_ptr();
         }
-        static mut HOLE: Lazy<TaskContext> = Lazy::new(TaskContext::default);
-        unsafe { __switch(HOLE.as_mut_ptr(), next, &mut 0u32 as *mut u32) };
+        unsafe { __switch(self.hole.as_ptr(), next, &mut 0u32 as *mut u32) };
         unreachable!()
     }
 
-    /// 如果传入 `tigger` 为 `Some` 则将当前任务置为 `Wait`
+    /// 如果传入 `tigger` 为 `Some` 则将当前任务置为 `Wait`。
+    /// 调度时关中断，任务恢复运行时还原中断状态
     #[inline]
     pub fn schedule(&self, tigger: Option<FutureBox>) {
-        let current_task = self.current.take().unwrap();
+        debug_assert_eq!(preempt_count(), 0, "schedule with preemption disabled");
+        let sie = sstatus::read().sie();
+        // 开中断运行时被推迟的中断在切换前处理，唤醒等待定时器的任务
+        unsafe {
+            sstatus::clear_sie();
+            handle_deferred_interrupts(false);
+        }
+        self.switch_out(tigger);
+        if sie {
+            unsafe { sstatus::set_sie() };
+        }
+    }
+
+    fn switch_out(&self, tigger: Option<FutureBox>) {
+        let current_task = self.take_current().unwrap();
+        current_task.account_time(false);
+        current_task.account_switch(tigger.is_some());
         let current = current_task.task_context();
+        let ran = get_time() - self.switch_time.get();
+        self.queue.queue.lock().account(&current_task, ran);
         // current_task.send_lock.store(TASK_SEND_LOCK, Ordering::SeqCst);
         let lock_addr = current_task.send_lock.as_ptr();
         current_task
             .send_lock
             .store(TASK_SEND_LOCK, Ordering::Relaxed);
+        if tigger.is_none() && !current_task.sched.lock().allowed_on(self.hartid) {
+            // 先切换到初始化栈，保存完上下文后再迁移到允许的处理器
+            self.migrating.set(Some(current_task));
+            unsafe { __switch(current, self.switch_trampoline.as_ptr(), lock_addr) };
+            return;
+        }
+        // 等待条件可能在阻塞前已经满足并且通知已被消耗，加入等待队列后至少轮询一次
+        if tigger.is_some() {
+            self.notify_wait();
+        }
         // 任务被存放到任务队列时必须确保该任务的上下文被保存完毕
         self.queue.push_task(current_task, tigger);
 
@@ -166,37 +315,73 @@ pub trait Schedule {
     fn yield_(&self);
 }
 
-impl Schedule for Processor {
+/// 当前硬件线程上运行的任务，系统调用和信号处理通过它访问调度器
+#[derive(Debug, Clone, Copy)]
+pub struct Current;
+
+impl Schedule for Current {
     #[inline]
     fn current_task(&self) -> Task {
-        unsafe { (*self.current.as_ptr()).clone().unwrap() }
+        current_task()
     }
+
     fn exit_current(&self, code: i32) -> ! {
-        self.current_task().exit(code);
-        self.entrap_task()
+        exit_current(code)
     }
 
     fn blocking_current<F>(&self, tigger: F)
     where
         F: Future<Output = ()> + Send + Sync + 'static,
     {
-        // 当前任务在被其它线程获取之前必须保存完 `TaskContext`
-        self.schedule(Some(Box::new(tigger)));
+        blocking_current(tigger)
     }
 
     fn yield_(&self) {
-        self.schedule(None);
+        yield_()
     }
 }
 
+// 任务阻塞后可能被迁移到其它处理器上继续运行，因此总是通过 `get_processor`
+// 访问当前硬件线程的 `Processor`
+
+#[inline]
+pub fn current_task() -> Task {
+    try_current_task().unwrap()
+}
+
+/// 内核启动时还没有当前任务
+#[inline]
+pub fn try_current_task() -> Option<Task> {
+    let this = get_processor();
+    unsafe { (*this.current.as_ptr()).clone() }
+}
+
+pub fn exit_current(code: i32) -> ! {
+    current_task().exit(code);
+    get_processor().entrap_task()
+}
+
+pub fn blocking_current<F>(tigger: F)
+where
+    F: Future<Output = ()> + Send + Sync + 'static,
+{
+    // 当前任务在被其它线程获取之前必须保存完 `TaskContext`
+    get_processor().schedule(Some(Box::new(tigger)));
+}
+
 pub fn yield_() {
-    get_processor().yield_()
+    get_processor().schedule(None);
+}
+
+/// 当前任务是否已被终止，循环等待的系统调用应当尽快返回
+pub fn current_killed() -> bool {
+    current_task().is_killed()
 }
 
 impl TaskQueue {
     pub fn new() -> Self {
         Self {
-            queue: Mutex::new(VecDeque::new()),
+            queue: Mutex::new(ClassQueue::new(new_policy())),
             wait_queue: Mutex::new(VecDeque::new()),
         }
     }
@@ -217,18 +402,25 @@ impl TaskQueue {
                 .push_back(BlockedTask::new(task, tigger))
         } else {
             task.set_state(TaskStatus::Ready);
-            self.queue.lock().push_back(task)
+            self.queue.lock().push(task)
         }
         // self.queue.lock().push_back(task);
     }
 
-    pub fn poll_all_wait(&self) -> bool {
+    /// 唤醒所有条件满足的任务，不允许在 `hartid` 上运行的任务被送往其它处理器
+    pub fn poll_all_wait(&self, hartid: usize) -> bool {
         let mut flag = false;
         let mut wait_queue = self.wait_queue.lock();
         for _ in 0..wait_queue.len() {
             if let Some(wait_task) = wait_queue.pop_front() {
                 if let Some(task) = wait_task.poll() {
-                    self.push_task(task, None);
+                    let migrate = !task.sched.lock().allowed_on(hartid)
+                        && task.send_lock.load(Ordering::Acquire) == TASK_SEND_UNLOCK;
+                    if migrate {
+                        GLOBAL_SCHEDULER.add_task(task);
+                    } else {
+                        self.push_task(task, None);
+                    }
                     flag = true;
                 } else {
                     wait_queue.push_back(wait_task);
@@ -240,6 +432,6 @@ impl TaskQueue {
 
     #[inline]
     pub fn pop_ready(&self) -> Option<Task> {
-        self.queue.lock().pop_front()
+        self.queue.lock().pop()
     }
 }
@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+
+use crate::{
+    config::{CLOCK_FREQ, PAGE_SIZE, USER_STACK_SIZE},
+    syscall::errno::{EINVAL, EPERM},
+};
+
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIM_NLIMITS: usize = 16;
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// 与 Linux `struct rlimit` 布局一致
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RLimit {
+    /// 软限制，进程可以在硬限制以内随意修改
+    pub cur: usize,
+    /// 硬限制，只有 root 可以提高
+    pub max: usize,
+}
+
+impl RLimit {
+    pub const INFINITY: Self = Self::new(RLIM_INFINITY, RLIM_INFINITY);
+
+    pub const fn new(cur: usize, max: usize) -> Self {
+        Self { cur, max }
+    }
+}
+
+/// CPU 时间限制对应的时钟周期数，为0表示不限制
+pub fn cpu_limit_ticks(secs: usize) -> usize {
+    match secs {
+        RLIM_INFINITY => 0,
+        // 限制为0时立即到期
+        secs => secs.saturating_mul(CLOCK_FREQ).max(1),
+    }
+}
+
+/// 进程的资源限制，fork 时继承，exec 时保持不变
+#[derive(Debug, Clone)]
+pub struct ResourceLimits {
+    limits: [RLimit; RLIM_NLIMITS],
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        let mut limits = [RLimit::INFINITY; RLIM_NLIMITS];
+        // 每个线程的用户栈最多为 `USER_STACK_SIZE`
+        limits[RLIMIT_STACK] = RLimit::new(USER_STACK_SIZE, RLIM_INFINITY);
+        limits[RLIMIT_NOFILE] = RLimit::new(1024, 4096);
+        limits[RLIMIT_CORE] = RLimit::new(0, RLIM_INFINITY);
+        Self { limits }
+    }
+}
+
+impl ResourceLimits {
+    pub fn get(&self, resource: usize) -> Result<RLimit> {
+        self.limits
+            .get(resource)
+            .copied()
+            .ok_or_else(|| EINVAL.with(anyhow!("invalid resource {}", resource)))
+    }
+
+    /// 当前生效的软限制
+    #[inline]
+    pub fn cur(&self, resource: usize) -> usize {
+        self.limits[resource].cur
+    }
+
+    /// 软限制不能超过硬限制，非特权进程不能提高硬限制，返回原来的限制
+    pub fn set(&mut self, resource: usize, limit: RLimit, privileged: bool) -> Result<RLimit> {
+        let old = self.get(resource)?;
+        if limit.cur > limit.max {
+            return Err(EINVAL.with("soft limit exceeds hard limit"));
+        }
+        if limit.max > old.max && !privileged {
+            return Err(EPERM.with("cannot raise hard limit"));
+        }
+        self.limits[resource] = limit;
+        Ok(old)
+    }
+
+    /// 线程用户栈的大小，按页向下对齐且不超过 `USER_STACK_SIZE`
+    pub fn stack_size(&self) -> usize {
+        (self.cur(RLIMIT_STACK).min(USER_STACK_SIZE) & !(PAGE_SIZE - 1)).max(PAGE_SIZE)
+    }
+}
@@ -0,0 +1,302 @@
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+};
+
+use anyhow::{anyhow, Result};
+use spin::Mutex;
+
+use crate::{
+    config::{CLOCK_FREQ, NUM_HARTS, TICK_FREQ},
+    timer::get_time,
+};
+
+use super::{policy::SchedPolicy, tcb::Task};
+
+pub const SCHED_NORMAL: u32 = 0;
+pub const SCHED_FIFO: u32 = 1;
+pub const SCHED_RR: u32 = 2;
+pub const SCHED_DEADLINE: u32 = 6;
+
+pub const RT_PRIO_MIN: u32 = 1;
+pub const RT_PRIO_MAX: u32 = 99;
+/// SCHED_RR 任务的时间片（时钟周期）
+pub const RR_TIMESLICE: usize = 10 * CLOCK_FREQ / TICK_FREQ;
+
+/// 带宽的定点数精度，`1 << BW_SHIFT` 表示占满一个处理器
+const BW_SHIFT: usize = 20;
+/// 每个硬件线程上截止期限任务的带宽之和不超过 95%
+const BW_LIMIT: usize = (95 << BW_SHIFT) / 100;
+/// 每个硬件线程上已分配给截止期限任务的带宽。截止期限任务按硬件线程分区调度，
+/// 每个分区的带宽不超过1时 EDF 能够满足所有截止期限
+static DL_BANDWIDTH: [Mutex<usize>; NUM_HARTS] = [const { Mutex::new(0) }; NUM_HARTS];
+
+/// 将硬件线程 `hartid` 上预留的带宽从 `old` 改为 `new`，超过限制时失败
+pub(super) fn dl_reserve(hartid: usize, old: usize, new: usize) -> bool {
+    let mut total = DL_BANDWIDTH[hartid].lock();
+    if *total - old + new > BW_LIMIT {
+        return false;
+    }
+    *total = *total - old + new;
+    true
+}
+
+pub(super) fn dl_load(hartid: usize) -> usize {
+    *DL_BANDWIDTH[hartid].lock()
+}
+
+/// 截止期限任务的参数，单位为时钟周期
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineParam {
+    /// 每个周期内可运行的时间
+    pub runtime: usize,
+    /// 相对截止期限
+    pub deadline: usize,
+    pub period: usize,
+}
+
+impl DeadlineParam {
+    pub fn new(runtime: usize, deadline: usize, period: usize) -> Result<Self> {
+        let period = if period == 0 { deadline } else { period };
+        if runtime == 0 || runtime > deadline || deadline > period {
+            return Err(anyhow!(
+                "invalid deadline param: runtime {} deadline {} period {}",
+                runtime,
+                deadline,
+                period
+            ));
+        }
+        Ok(Self {
+            runtime,
+            deadline,
+            period,
+        })
+    }
+
+    /// 按密度 `runtime / deadline` 计算，截止期限小于周期时也能保证可调度
+    pub(super) fn bandwidth(&self) -> usize {
+        ((self.runtime as u128) << BW_SHIFT).div_ceil(self.deadline as u128) as usize
+    }
+}
+
+/// 调度类，优先级从高到低依次为截止期限、实时、普通
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SchedClass {
+    #[default]
+    Normal,
+    /// 固定优先级，不会因时间片用完而被抢占
+    Fifo(u32),
+    /// 固定优先级，同优先级任务按时间片轮转
+    RoundRobin(u32),
+    /// 最早截止期限优先
+    Deadline(DeadlineParam),
+}
+
+impl SchedClass {
+    pub fn policy(&self) -> u32 {
+        match self {
+            Self::Normal => SCHED_NORMAL,
+            Self::Fifo(_) => SCHED_FIFO,
+            Self::RoundRobin(_) => SCHED_RR,
+            Self::Deadline(_) => SCHED_DEADLINE,
+        }
+    }
+
+    pub fn rt_priority(&self) -> u32 {
+        match self {
+            Self::Fifo(prio) | Self::RoundRobin(prio) => *prio,
+            _ => 0,
+        }
+    }
+
+    pub fn from_policy(policy: u32, priority: u32) -> Result<Self> {
+        let rt_prio = || {
+            if (RT_PRIO_MIN..=RT_PRIO_MAX).contains(&priority) {
+                Ok(priority)
+            } else {
+                Err(anyhow!("invalid rt priority {}", priority))
+            }
+        };
+        match policy {
+            SCHED_NORMAL if priority == 0 => Ok(Self::Normal),
+            SCHED_FIFO => Ok(Self::Fifo(rt_prio()?)),
+            SCHED_RR => Ok(Self::RoundRobin(rt_prio()?)),
+            _ => Err(anyhow!(
+                "invalid sched policy {} priority {}",
+                policy,
+                priority
+            )),
+        }
+    }
+}
+
+/// 实时任务队列，每个优先级一个先进先出队列
+#[derive(Default)]
+struct RtQueue {
+    queues: BTreeMap<u32, VecDeque<Task>>,
+}
+
+impl RtQueue {
+    fn push(&mut self, prio: u32, task: Task, front: bool) {
+        let queue = self.queues.entry(prio).or_default();
+        if front {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Task> {
+        let mut entry = self.queues.last_entry()?;
+        let task = entry.get_mut().pop_front();
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        task
+    }
+
+    fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task> {
+        let (&prio, queue) = self
+            .queues
+            .iter_mut()
+            .find(|(_, queue)| queue.iter().any(pred))?;
+        let idx = queue.iter().position(pred)?;
+        let task = queue.remove(idx);
+        if queue.is_empty() {
+            self.queues.remove(&prio);
+        }
+        task
+    }
+
+    fn top_priority(&self) -> Option<u32> {
+        self.queues.last_key_value().map(|(&prio, _)| prio)
+    }
+
+    fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+}
+
+/// 截止期限任务队列，按绝对截止期限排序
+#[derive(Default)]
+struct DeadlineQueue {
+    queue: BTreeMap<(usize, usize), Task>,
+    seq: usize,
+}
+
+impl DeadlineQueue {
+    fn push(&mut self, param: &DeadlineParam, task: Task) {
+        let abs_deadline = {
+            let mut sched = task.sched.lock();
+            let now = get_time();
+            // 截止期限已过或运行时间耗尽时开始新的周期
+            if sched.runtime_left == 0 || now >= sched.abs_deadline {
+                sched.abs_deadline = now + param.deadline;
+                sched.runtime_left = param.runtime;
+            }
+            sched.abs_deadline
+        };
+        self.seq += 1;
+        self.queue.insert((abs_deadline, self.seq), task);
+    }
+
+    fn pop(&mut self) -> Option<Task> {
+        self.queue.pop_first().map(|(_, task)| task)
+    }
+
+    fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task> {
+        let key = *self.queue.iter().find(|&(_, task)| pred(task))?.0;
+        self.queue.remove(&key)
+    }
+
+    fn earliest_deadline(&self) -> Option<usize> {
+        self.queue
+            .first_key_value()
+            .map(|(&(deadline, _), _)| deadline)
+    }
+}
+
+/// 按调度类分层的就绪队列，普通任务交给 `SchedPolicy` 调度
+pub struct ClassQueue {
+    dl: DeadlineQueue,
+    rt: RtQueue,
+    normal: Box<dyn SchedPolicy>,
+}
+
+impl ClassQueue {
+    pub fn new(normal: Box<dyn SchedPolicy>) -> Self {
+        Self {
+            dl: DeadlineQueue::default(),
+            rt: RtQueue::default(),
+            normal,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.normal.name()
+    }
+
+    pub fn push(&mut self, task: Task) {
+        let (class, preempted) = {
+            let mut sched = task.sched.lock();
+            (sched.class, core::mem::take(&mut sched.preempted))
+        };
+        match class {
+            SchedClass::Normal => self.normal.push(task),
+            // 被抢占的实时任务回到同优先级队列的队首
+            SchedClass::Fifo(prio) | SchedClass::RoundRobin(prio) => {
+                self.rt.push(prio, task, preempted)
+            }
+            SchedClass::Deadline(param) => self.dl.push(&param, task),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Task> {
+        self.dl
+            .pop()
+            .or_else(|| self.rt.pop())
+            .or_else(|| self.normal.pop())
+    }
+
+    pub fn steal(&mut self, pred: &dyn Fn(&Task) -> bool) -> Option<Task> {
+        self.normal
+            .steal(pred)
+            .or_else(|| self.rt.steal(pred))
+            .or_else(|| self.dl.steal(pred))
+    }
+
+    pub fn len(&self) -> usize {
+        self.dl.queue.len() + self.rt.len() + self.normal.len()
+    }
+
+    pub fn account(&mut self, task: &Task, ticks: usize) {
+        let class = task.sched.lock().class;
+        match class {
+            SchedClass::Normal => self.normal.account(task, ticks),
+            SchedClass::Deadline(_) => {
+                let mut sched = task.sched.lock();
+                sched.runtime_left = sched.runtime_left.saturating_sub(ticks);
+            }
+            _ => {}
+        }
+    }
+
+    /// 就绪队列中是否有应当抢占 `task` 的任务
+    pub fn should_preempt(&self, task: &Task) -> bool {
+        let (class, abs_deadline) = {
+            let sched = task.sched.lock();
+            (sched.class, sched.abs_deadline)
+        };
+        if let Some(deadline) = self.dl.earliest_deadline() {
+            return match class {
+                SchedClass::Deadline(_) => deadline < abs_deadline,
+                _ => true,
+            };
+        }
+        match (self.rt.top_priority(), class) {
+            (None, _) | (_, SchedClass::Deadline(_)) => false,
+            (Some(_), SchedClass::Normal) => true,
+            (Some(top), SchedClass::Fifo(prio) | SchedClass::RoundRobin(prio)) => top > prio,
+        }
+    }
+}
@@ -1,6 +1,11 @@
 use super::{processor::Processor, tcb::Task};
-use crate::{config::NUM_HARTS, sbi::get_hartid};
+use crate::{
+    config::NUM_HARTS,
+    ipi::{send_ipi, IpiMessage},
+    sbi::get_hartid,
+};
 use alloc::vec::Vec;
+use log::info;
 use spin::Lazy;
 
 pub static GLOBAL_SCHEDULER: Lazy<Scheduler> = Lazy::new(|| Scheduler::new(NUM_HARTS));
@@ -18,6 +23,7 @@ impl Scheduler {
         for hartid in 0..hart_num {
             group.push(Processor::new(hartid));
         }
+        info!("scheduler policy: {}", group[0].policy_name());
         Self { group }
     }
 
@@ -27,31 +33,46 @@ impl Scheduler {
         &self.group[hartid]
     }
 
+    /// 将任务加入允许运行的处理器中就绪任务最少的一个
     pub fn add_task(&self, task: Task) {
-        // unsafe { task.trap_context().hartid = 0 };
-        // self.group[0].add_task(task);
+        let affinity = task.sched.lock().hart_mask();
         let (hartid, processor) = self
             .group
             .iter()
             .enumerate()
-            .min_by(|(_, x), (_, y)| x.ready_task_num().cmp(&y.ready_task_num()))
+            .filter(|&(hartid, _)| affinity & (1 << hartid) != 0)
+            .min_by_key(|(_, processor)| processor.ready_task_num())
             .unwrap();
-        unsafe { task.trap_context().hartid = hartid };
         processor.add_task(task);
+        if hartid == get_hartid() {
+            processor.update_tick();
+        } else {
+            // 目标处理器可能处于空闲状态或关闭了调度时钟
+            send_ipi(hartid, IpiMessage::Wakeup);
+        }
     }
 
-    pub fn fetch_task(&self) -> Option<Task> {
-        let iter = self.group.iter();
-        if let Some(processor) = iter.max_by(|x, y| x.ready_task_num().cmp(&y.ready_task_num())) {
-            processor.fetch_task()
-        } else {
-            None
+    /// 从就绪任务最多的处理器上取出一个任务给 `hartid`，
+    /// 两者就绪任务数之差至少为 `imbalance`
+    pub fn fetch_task(&self, hartid: usize, imbalance: usize) -> Option<Task> {
+        let local = self.group[hartid].ready_task_num();
+        let (busiest, num) = self
+            .group
+            .iter()
+            .enumerate()
+            .filter(|&(id, _)| id != hartid)
+            .map(|(_, processor)| (processor, processor.ready_task_num()))
+            .max_by_key(|&(_, num)| num)?;
+        if num < local + imbalance {
+            return None;
         }
+        busiest.fetch_task(hartid)
     }
 
-    pub fn balance(&self) {
-        if let Some(task) = self.fetch_task() {
-            self.add_task(task)
+    /// 就绪任务数相差超过1时，将一个任务迁移到 `hartid`
+    pub fn balance(&self, hartid: usize) {
+        if let Some(task) = self.fetch_task(hartid, 2) {
+            self.group[hartid].add_task(task)
         }
     }
 }
@@ -1,32 +1,193 @@
-use core::ops::{Index, IndexMut};
+use core::{
+    arch::naked_asm,
+    mem::{offset_of, size_of, MaybeUninit},
+    ops::{Index, IndexMut},
+};
 
-use alloc::boxed::Box;
+use anyhow::{anyhow, Result};
 use bitflags::bitflags;
+use log::warn;
 
-use super::{processor::Schedule, tcb::TaskControlBlock, tigger::SignalWaiter};
+use crate::{
+    config::SIGRETURN_TRAMPOLINE,
+    mm::page_table::{copy_from_user, copy_to_user},
+    syscall::errno::{EINTR, EINVAL, ENOMEM, EPERM},
+    trap::context::TrapContext,
+};
+
+use super::{
+    process::{signal_status, stop_status, ProcessSharedStatus, CONTINUED_STATUS},
+    processor::Schedule,
+    tcb::{SharedStatus, TaskControlBlock},
+    tigger::SignalWaiter,
+};
 
 pub const MAX_SIG: usize = 31;
 
+/// 默认处理方式
+pub const SIG_DFL: usize = 0;
+/// 忽略信号
+pub const SIG_IGN: usize = 1;
+
 bitflags! {
-    #[derive(Default, Clone, Copy, PartialEq, Eq)]
+    /// 第 `n` 位对应信号 `n`，与 Linux 信号编号一致
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
     pub struct SignalFlags: u32 {
-        const SIGDEF    = 1 << 0; // Default signal handling
+        const SIGHUP    = 1 << 1;
+        const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
+        const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
+        const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
+        const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
+        const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
         const SIGCONT   = 1 << 18;
         const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// 终止并在等待状态中标记 core dump
+    CoreDump,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+impl SignalFlags {
+    /// 不能被捕获、忽略或屏蔽的信号
+    pub const UNCATCHABLE: Self = Self::SIGKILL.union(Self::SIGSTOP);
+
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if (1..=MAX_SIG).contains(&signum) {
+            Some(Self::from_bits_retain(1 << signum))
+        } else {
+            None
+        }
+    }
+
+    /// 编号最小的信号
+    pub fn signum(self) -> usize {
+        self.bits().trailing_zeros() as usize
+    }
+
+    /// 取出编号最小的信号
+    pub fn pop(&mut self) -> Option<Self> {
+        let flag = Self::from_signum(self.signum())?;
+        self.remove(flag);
+        Some(flag)
+    }
+
+    pub fn is_uncatchable(self) -> bool {
+        Self::UNCATCHABLE.contains(self)
+    }
+
+    pub fn default_action(self) -> DefaultAction {
+        let ignore = Self::SIGCHLD.union(Self::SIGURG).union(Self::SIGWINCH);
+        let stop = Self::SIGSTOP
+            .union(Self::SIGTSTP)
+            .union(Self::SIGTTIN)
+            .union(Self::SIGTTOU);
+        let core = Self::SIGQUIT
+            .union(Self::SIGILL)
+            .union(Self::SIGTRAP)
+            .union(Self::SIGABRT)
+            .union(Self::SIGBUS)
+            .union(Self::SIGFPE)
+            .union(Self::SIGSEGV)
+            .union(Self::SIGXCPU)
+            .union(Self::SIGXFSZ)
+            .union(Self::SIGSYS);
+        if ignore.contains(self) {
+            DefaultAction::Ignore
+        } else if self == Self::SIGCONT {
+            DefaultAction::Continue
+        } else if stop.contains(self) {
+            DefaultAction::Stop
+        } else if core.contains(self) {
+            DefaultAction::CoreDump
+        } else {
+            DefaultAction::Terminate
+        }
     }
 }
 
-pub fn is_handle_by_kernel(flag: SignalFlags) -> bool {
-    [SignalFlags::SIGSTOP, SignalFlags::SIGCONT].contains(&flag)
+bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct SigActionFlags: usize {
+        /// 处理函数接收 `siginfo_t` 和 `ucontext_t`，总是传入，仅用于兼容
+        const SA_SIGINFO   = 0x0000_0004;
+        /// 在备用信号栈上执行处理函数
+        const SA_ONSTACK   = 0x0800_0000;
+        /// 被中断的系统调用自动重新执行
+        const SA_RESTART   = 0x1000_0000;
+        /// 执行处理函数时不屏蔽当前信号
+        const SA_NODEFER   = 0x4000_0000;
+        /// 递送一次后恢复默认处理方式
+        const SA_RESETHAND = 0x8000_0000;
+    }
+}
+
+/// 与 Linux `struct sigaction` 布局一致，RISC-V 上没有 `sa_restorer`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: u64,
 }
 
-#[derive(Debug, Default)]
+impl SigAction {
+    pub fn flags(&self) -> SigActionFlags {
+        SigActionFlags::from_bits_truncate(self.flags)
+    }
+
+    pub fn mask(&self) -> SignalFlags {
+        SignalFlags::from_sigset(self.mask)
+    }
+}
+
+impl SignalFlags {
+    /// 用户态的 `sigset_t` 中信号 `n` 对应第 `n - 1` 位，不支持实时信号
+    pub fn from_sigset(set: u64) -> Self {
+        Self::from_bits_truncate((set << 1) as u32)
+    }
+
+    pub fn to_sigset(self) -> u64 {
+        (self.bits() >> 1) as u64
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct SignalActions {
-    pub table: [usize; MAX_SIG + 1],
+    pub table: [SigAction; MAX_SIG + 1],
 }
 
 impl Index<usize> for SignalActions {
-    type Output = usize;
+    type Output = SigAction;
 
     fn index(&self, index: usize) -> &Self::Output {
         &self.table[index]
@@ -40,65 +201,343 @@ impl IndexMut<usize> for SignalActions {
 }
 
 impl SignalActions {
-    pub fn get(&self, index: usize) -> Option<&usize> {
+    pub fn get(&self, index: usize) -> Option<&SigAction> {
         self.table.get(index)
     }
+
+    /// exec 后用户处理函数失效，被忽略的信号保持忽略
+    pub fn reset_handlers(&mut self) {
+        for action in self.table.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
 }
 
-#[derive(Default)]
+/// 信号处理函数在进程的线程和 `CLONE_SIGHAND` 创建的进程间共享，屏蔽字和待处理信号属于各个线程
+#[derive(Default, Clone)]
 pub struct Signal {
-    /// 全局信号标志位，不允许嵌套信号处理
-    pub global_mask: bool,
-    pub mask: SignalFlags,
     pub actions: SignalActions,
 }
 
+/// 与 Linux `stack_t` 布局一致
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SignalStack {
+    pub sp: usize,
+    pub flags: i32,
+    pub size: usize,
+}
+
+/// 当前正在备用信号栈上执行
+pub const SS_ONSTACK: i32 = 1;
+/// 禁用备用信号栈
+pub const SS_DISABLE: i32 = 2;
+/// 备用信号栈的最小大小
+pub const MINSIGSTKSZ: usize = 2048;
+
+impl SignalStack {
+    pub fn disabled() -> Self {
+        Self {
+            flags: SS_DISABLE,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.flags & SS_DISABLE == 0
+    }
+
+    /// `sp` 是否位于备用信号栈上，栈向下增长，栈顶为 `sp + size`
+    pub fn contains(&self, sp: usize) -> bool {
+        self.is_enabled() && sp > self.sp && sp - self.sp <= self.size
+    }
+
+    /// 返回给用户的状态，`sp` 为用户当前的栈指针
+    pub fn status(&self, sp: usize) -> Self {
+        let flags = if self.contains(sp) {
+            SS_ONSTACK
+        } else {
+            self.flags & SS_DISABLE
+        };
+        Self { flags, ..*self }
+    }
+}
+
+/// 与 Linux `siginfo_t` 布局一致，`fields` 的第一个字为 `si_addr`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    _pad: i32,
+    pub fields: [usize; 14],
+}
+
+/// 由 `kill` 发送
+const SI_USER: i32 = 0;
+/// 由异常产生，对应 `SEGV_MAPERR`、`ILL_ILLOPC`、`BUS_ADRALN` 和 `TRAP_BRKPT`
+const SI_FAULT: i32 = 1;
+
+/// 与 Linux `struct sigcontext` 布局一致，依次保存 pc 和 x1 到 x31，不保存浮点寄存器
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct MContext {
+    pub regs: [usize; 32],
+    pub fpregs: [u64; 66],
+}
+
+/// 与 Linux `ucontext_t` 布局一致
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SignalStack,
+    pub sigmask: u64,
+    _unused: [u8; 120],
+    pub mcontext: MContext,
+}
+
+/// 压入用户栈的信号帧，处理函数的第二、三个参数分别指向 `info` 和 `uc`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigFrame {
+    pub info: SigInfo,
+    pub uc: UContext,
+}
+
+impl MContext {
+    fn save(&mut self, trap_cx: &TrapContext) {
+        let reg = &trap_cx.reg_file;
+        self.regs[0] = trap_cx.sepc;
+        self.regs[1..5].copy_from_slice(&[reg.ra, reg.sp, reg.gp, reg.tp]);
+        self.regs[5..8].copy_from_slice(&reg.t[..3]);
+        self.regs[8..10].copy_from_slice(&reg.s[..2]);
+        self.regs[10..18].copy_from_slice(&reg.a);
+        self.regs[18..28].copy_from_slice(&reg.s[2..]);
+        self.regs[28..32].copy_from_slice(&reg.t[3..]);
+    }
+
+    fn restore(&self, trap_cx: &mut TrapContext) {
+        let reg = &mut trap_cx.reg_file;
+        trap_cx.sepc = self.regs[0];
+        [reg.ra, reg.sp, reg.gp, reg.tp] = [self.regs[1], self.regs[2], self.regs[3], self.regs[4]];
+        reg.t[..3].copy_from_slice(&self.regs[5..8]);
+        reg.s[..2].copy_from_slice(&self.regs[8..10]);
+        reg.a.copy_from_slice(&self.regs[10..18]);
+        reg.s[2..].copy_from_slice(&self.regs[18..28]);
+        reg.t[3..].copy_from_slice(&self.regs[28..32]);
+    }
+}
+
+/// 信号处理函数返回到该跳板，由它调用 `rt_sigreturn`
+#[naked]
+#[link_section = ".text.sigreturn"]
+pub unsafe extern "C" fn user_sigreturn() {
+    naked_asm! {r"
+        li a7, 139
+        ecall
+        ",
+        options()
+    }
+}
+
 pub trait SignalHandle {
-    fn handle_signals(&self);
+    /// `interrupted` 为返回 `ERESTARTSYS` 的系统调用的第一个参数，用于重新执行该系统调用。
+    /// 只有处理了信号时才重新执行，调用没有 `SA_RESTART` 的用户处理函数或没有信号时返回 `EINTR`
+    fn handle_signals(&self, interrupted: Option<usize>);
 }
 
 impl<T> SignalHandle for T
 where
     T: Schedule,
 {
-    fn handle_signals(&self) {
+    fn handle_signals(&self, interrupted: Option<usize>) {
         let task = self.current_task();
-        let local = task.process.inner.read();
-        let signals = *task.shared.signals.lock();
-        for signal in [0, 18, 19] {
-            let flag = SignalFlags::from_bits_truncate(1 << signal as u32);
-            if (signals & local.signal.mask).contains(flag) {
-                match flag {
-                    SignalFlags::SIGSTOP => {
+        let mut taken = false;
+        while let Some(flag) = task.take_signal() {
+            taken = true;
+            let signum = flag.signum();
+            let action = task.process.inner.read().signal.lock().actions[signum];
+            match action.handler {
+                SIG_IGN => (),
+                SIG_DFL => match flag.default_action() {
+                    // `SIGCONT` 在发送时已经唤醒停止的线程
+                    DefaultAction::Ignore | DefaultAction::Continue => (),
+                    DefaultAction::Stop => {
+                        task.process.report_event(stop_status(signum));
                         self.blocking_current(SignalWaiter::new(&task, SignalFlags::SIGCONT));
+                        if !task.is_killed() {
+                            task.process.report_event(CONTINUED_STATUS);
+                        }
                     }
-                    // `SIGCONT` 信号由 `tigger` 处理
-                    SignalFlags::SIGCONT => (),
-                    _ => {
-                        task.set_user_signal_sret(signal);
+                    action => {
+                        let core = action == DefaultAction::CoreDump;
+                        task.process.terminate(signal_status(signum, core));
                         return;
                     }
+                },
+                _ => {
+                    if let Some(a0) = interrupted {
+                        if action.flags().contains(SigActionFlags::SA_RESTART) {
+                            task.restart_syscall(a0);
+                        } else {
+                            task.interrupt_syscall();
+                        }
+                    }
+                    task.deliver_signal(flag, action);
+                    return;
                 }
             }
         }
+        // 信号被忽略或进程停止后继续时对用户态不可见，重新执行系统调用
+        if let Some(a0) = interrupted {
+            if taken {
+                task.restart_syscall(a0);
+            } else {
+                task.interrupt_syscall();
+            }
+        }
+    }
+}
+
+impl SharedStatus {
+    /// 是否有未被屏蔽的待处理信号，用于中断可被信号打断的阻塞
+    pub fn has_signal(&self, process: &ProcessSharedStatus) -> bool {
+        let blocked = *self.sigmask.lock() - SignalFlags::UNCATCHABLE;
+        let pending = *self.signals.lock() - SignalFlags::SIGCONT;
+        !(pending - blocked).is_empty() || !(*process.signals.lock() - blocked).is_empty()
     }
 }
 
 impl TaskControlBlock {
-    pub fn set_user_signal_sret(&self, signal: usize) {
-        let mut process = self.process.inner.write();
-        let handler = process.signal.actions[signal];
-        if handler != 0 {
-            // 关闭信号接收
-            process.signal.global_mask = false;
-            // 关闭信号标志位
-            *self.shared.signals.lock() ^= SignalFlags::from_bits_truncate(1 << signal);
-
-            // 备份 trap context ，设置用户信号处理函数入口
-            let trap_cx = unsafe { self.trap_context() };
-            self.local.borrow_mut().trap_cx_backup = Some(Box::new(*trap_cx));
-            trap_cx.sepc = handler;
-            trap_cx.set_return(signal);
+    /// 取出一个未被屏蔽的待处理信号，线程的信号优先于进程的信号
+    pub fn take_signal(&self) -> Option<SignalFlags> {
+        if self.is_killed() {
+            return None;
+        }
+        let blocked = *self.shared.sigmask.lock() - SignalFlags::UNCATCHABLE;
+        let mut signals = self.shared.signals.lock();
+        // 线程的 `SIGCONT` 只用于唤醒 `SignalWaiter`
+        signals.remove(SignalFlags::SIGCONT);
+        if let Some(flag) = (*signals - blocked).pop() {
+            signals.remove(flag);
+            return Some(flag);
+        }
+        drop(signals);
+        let mut signals = self.process.shared.signals.lock();
+        let flag = (*signals - blocked).pop()?;
+        signals.remove(flag);
+        Some(flag)
+    }
+
+    /// 由当前线程的异常产生的信号，`addr` 为出错地址，无法递送给用户处理函数时直接终止进程
+    pub fn force_signal(&self, flag: SignalFlags, addr: usize) {
+        let signum = flag.signum();
+        let handler = self.process.inner.read().signal.lock().actions[signum].handler;
+        if handler > SIG_IGN && !self.shared.sigmask.lock().contains(flag) {
+            self.local.borrow_mut().fault = Some((flag, addr));
+            self.shared.signals.lock().insert(flag);
+        } else {
+            let core = flag.default_action() == DefaultAction::CoreDump;
+            self.process.terminate(signal_status(signum, core));
         }
     }
+
+    /// 让被中断的系统调用返回用户态后重新执行
+    fn restart_syscall(&self, a0: usize) {
+        let trap_cx = unsafe { self.trap_context() };
+        trap_cx.sepc -= 4;
+        trap_cx.reg_file.a[0] = a0;
+    }
+
+    /// 被中断的系统调用不再重新执行，向用户态返回 `EINTR`
+    fn interrupt_syscall(&self) {
+        let trap_cx = unsafe { self.trap_context() };
+        trap_cx.reg_file.a[0] = -EINTR.0 as usize;
+    }
+
+    /// 在用户栈上构造信号帧并跳转到用户处理函数
+    pub fn deliver_signal(&self, flag: SignalFlags, action: SigAction) {
+        let signum = flag.signum();
+        let trap_cx = unsafe { self.trap_context() };
+        let mask = *self.shared.sigmask.lock();
+        let mut frame: SigFrame = unsafe { MaybeUninit::zeroed().assume_init() };
+        frame.info.signo = signum as i32;
+        frame.info.code = SI_USER;
+        if let Some((fault, addr)) = self.local.borrow_mut().fault.take() {
+            if fault == flag {
+                frame.info.code = SI_FAULT;
+                frame.info.fields[0] = addr;
+            }
+        }
+        frame.uc.sigmask = mask.to_sigset();
+        frame.uc.mcontext.save(trap_cx);
+        let altstack = self.local.borrow().sigaltstack;
+        let usp = trap_cx.reg_file.sp;
+        frame.uc.stack = altstack.status(usp);
+        // 已经在备用信号栈上时嵌套的处理函数继续使用当前栈
+        let usp = if action.flags().contains(SigActionFlags::SA_ONSTACK)
+            && altstack.is_enabled()
+            && !altstack.contains(usp)
+        {
+            altstack.sp + altstack.size
+        } else {
+            usp
+        };
+        let sp = usp.wrapping_sub(size_of::<SigFrame>()) & !0xf;
+        if let Err(err) = unsafe { copy_to_user(self.space(), sp.into(), &frame) } {
+            warn!("failed to push signal frame: {}", err);
+            let signum = SignalFlags::SIGSEGV.signum();
+            self.process.terminate(signal_status(signum, true));
+            return;
+        }
+        let mut new_mask = mask | action.mask();
+        if !action.flags().contains(SigActionFlags::SA_NODEFER) {
+            new_mask |= flag;
+        }
+        *self.shared.sigmask.lock() = new_mask - SignalFlags::UNCATCHABLE;
+        if action.flags().contains(SigActionFlags::SA_RESETHAND) {
+            self.process.inner.read().signal.lock().actions[signum] = SigAction::default();
+        }
+        trap_cx.sepc = action.handler;
+        trap_cx.reg_file.ra = SIGRETURN_TRAMPOLINE;
+        trap_cx.reg_file.sp = sp;
+        trap_cx.reg_file.a[0] = signum;
+        trap_cx.reg_file.a[1] = sp + offset_of!(SigFrame, info);
+        trap_cx.reg_file.a[2] = sp + offset_of!(SigFrame, uc);
+    }
+
+    /// 从用户栈上的信号帧恢复寄存器和屏蔽字
+    pub fn restore_signal_frame(&self) -> Result<()> {
+        let trap_cx = unsafe { self.trap_context() };
+        let frame: SigFrame = unsafe { copy_from_user(self.space(), trap_cx.reg_file.sp.into())? };
+        frame.uc.mcontext.restore(trap_cx);
+        *self.shared.sigmask.lock() =
+            SignalFlags::from_sigset(frame.uc.sigmask) - SignalFlags::UNCATCHABLE;
+        // 处理函数可能修改了帧中的备用信号栈，不合法的设置被忽略
+        let _ = self.set_sigaltstack(&frame.uc.stack);
+        Ok(())
+    }
+
+    /// 设置备用信号栈，正在备用信号栈上执行时不允许修改
+    pub fn set_sigaltstack(&self, stack: &SignalStack) -> Result<()> {
+        let usp = unsafe { self.trap_context().reg_file.sp };
+        let mut local = self.local.borrow_mut();
+        if local.sigaltstack.contains(usp) {
+            return Err(EPERM.with("changing the alternate signal stack while on it"));
+        }
+        local.sigaltstack = match stack.flags & !SS_ONSTACK {
+            SS_DISABLE => SignalStack::disabled(),
+            0 if stack.size < MINSIGSTKSZ => {
+                return Err(ENOMEM.with(anyhow!("signal stack size {:#x}", stack.size)));
+            }
+            0 => SignalStack { flags: 0, ..*stack },
+            flags => return Err(EINVAL.with(anyhow!("signal stack flags {:#x}", flags))),
+        };
+        Ok(())
+    }
 }
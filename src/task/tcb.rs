@@ -1,32 +1,39 @@
 use core::{
     cell::RefCell,
     fmt::Debug,
-    mem::{align_of, size_of},
+    mem::{self, align_of, size_of},
     ops::Range,
-    sync::atomic::AtomicU32,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, string::String, sync::Arc};
 
-use os_tools::OsStr;
-use spin::Mutex;
+use log::debug;
+use spin::{Mutex, RwLock};
 
 use crate::{
-    config::{GUARD_PAGE_SIZE, TRAP_CONTEXT, USER_STACK_SIZE},
+    config::{GUARD_PAGE_SIZE, PAGE_SIZE, TRAP_CONTEXT, USER_STACK_SIZE},
+    ipi::wakeup_all,
     mm::{
         address::VirtAddr,
-        memory_set::{kernel_token, MapArea, MapPerm, MapType, MemorySet},
-        page_table::{translated_byte_buffer, BufferHandle},
+        memory_set::{kernel_token, ElfLoadInfo, MapArea, MapPerm, MapType, MemorySet},
+        page_table::copy_to_user,
     },
+    timer::get_time,
     tools::align_ceil,
     trap::context::TrapContext,
 };
 
 use super::{
+    auxv::push_startup,
     context::{Context, TaskContext},
-    process::{Process, ProcessControlBlock},
-    signal::SignalFlags,
-    uid::{kstack_alloc, KernelStack},
+    kthread::KernelEntry,
+    policy::SchedEntity,
+    process::{exit_status, CloneFlags, Process, ProcessControlBlock},
+    processor::try_current_task,
+    signal::{SignalFlags, SignalStack},
+    uid::{kstack_alloc, KernelStack, Pid},
+    usage::Usage,
 };
 
 pub type Task = Arc<TaskControlBlock>;
@@ -44,17 +51,21 @@ pub struct TaskControlBlock {
     pub local: RefCell<ThreadLocal>,
     /// 线程间发送任务的锁，如果为0则可以在线程间发送任务。
     pub send_lock: AtomicU32,
+    /// 调度实体，由调度策略维护
+    pub sched: Mutex<SchedEntity>,
 }
 
 impl Drop for TaskControlBlock {
     fn drop(&mut self) {
-        ustack_dealloc(
-            &mut self.process.inner.write().memory_set,
-            self.local.borrow().ustack,
-        );
-        // 主线程退出则进程退出
-        if self.tid == 0 {
-            self.process.exit(self.shared.exit_code.lock().unwrap());
+        // 被终止的线程的用户栈和 trap 上下文随地址空间一起释放，内核进程的地址空间不会释放
+        if !self.is_killed() || self.process.is_kernel() {
+            let memory_set = self.process.inner.read().memory_set.clone();
+            let mut memory_set = memory_set.write();
+            let local = self.local.borrow();
+            memory_set.trap_slots.remove(local.trap_slot);
+            if let Some(ustack) = local.ustack {
+                ustack_dealloc(&mut memory_set, ustack);
+            }
         }
     }
 }
@@ -62,20 +73,57 @@ impl Drop for TaskControlBlock {
 pub struct ThreadLocal {
     _ksp: KernelStack,
     token: usize,
-    ustack: VirtAddr,
+    /// 内核分配的用户栈，clone 创建的线程使用调用者提供的栈
+    ustack: Option<VirtAddr>,
+    /// trap 上下文在地址空间中的槽位
+    trap_slot: usize,
     context: Context,
-    pub trap_cx_backup: Option<Box<TrapContext>>,
+    /// 由异常产生且尚未递送的信号和出错地址
+    pub fault: Option<(SignalFlags, usize)>,
+    /// 备用信号栈，exec 后被禁用
+    pub sigaltstack: SignalStack,
+    /// 上一次统计 CPU 时间的时刻
+    timestamp: usize,
+    /// 线程退出或 exec 时清零该地址处的 tid，为0表示不清零
+    pub clear_child_tid: usize,
+    /// 与 pid 在同一空间分配的线程 id，为空时与进程的 pid 相同
+    pub thread_id: Option<Pid>,
 }
 
-fn trap_context_addr(tid: usize) -> usize {
+/// 每页存放整数个 trap 上下文
+fn trap_context_addr(slot: usize) -> usize {
     let align = align_of::<TrapContext>().max(8);
     assert!(TRAP_CONTEXT % align == 0);
-    TRAP_CONTEXT + tid * align_ceil(size_of::<TrapContext>(), align)
+    let size = align_ceil(size_of::<TrapContext>(), align);
+    let per_page = PAGE_SIZE / size;
+    TRAP_CONTEXT + slot / per_page * PAGE_SIZE + slot % per_page * size
+}
+
+/// 分配空闲的 trap 上下文槽位，所在页尚未映射时映射该页
+fn trap_context_alloc(memory_set: &mut MemorySet) -> usize {
+    let slot = memory_set.trap_slots.push(());
+    let va = VirtAddr::from(trap_context_addr(slot));
+    if !memory_set
+        .translate(va.floor())
+        .is_some_and(|pte| pte.is_valid())
+    {
+        memory_set.push(
+            MapArea::new(
+                va,
+                (usize::from(va) + 1).into(),
+                MapPerm::RW,
+                MapType::Framed,
+            ),
+            None,
+        );
+    }
+    slot
 }
 
-fn user_stack_addr(tid: usize, ustack_base: usize) -> Range<VirtAddr> {
+/// 每个线程占用固定的栈空间，实际映射的大小 `size` 由 `RLIMIT_STACK` 决定
+fn user_stack_addr(tid: usize, ustack_base: usize, size: usize) -> Range<VirtAddr> {
     let bottom = ustack_base + (tid + 1) * (USER_STACK_SIZE + GUARD_PAGE_SIZE);
-    let top = bottom - USER_STACK_SIZE;
+    let top = bottom - size;
     top.into()..bottom.into()
 }
 
@@ -91,17 +139,33 @@ fn ustack_dealloc(memory_set: &mut MemorySet, bottom: VirtAddr) {
 }
 
 impl ThreadLocal {
-    pub fn new(context: Context, ksp: KernelStack, ustack: VirtAddr, token: usize) -> Self {
+    pub fn new(
+        context: Context,
+        ksp: KernelStack,
+        ustack: Option<VirtAddr>,
+        trap_slot: usize,
+        token: usize,
+    ) -> Self {
         Self {
             _ksp: ksp,
             ustack,
+            trap_slot,
             context,
             token,
-            trap_cx_backup: None,
+            fault: None,
+            sigaltstack: SignalStack::disabled(),
+            timestamp: get_time(),
+            clear_child_tid: 0,
+            thread_id: None,
         }
     }
 }
 
+/// 新任务从创建它的任务继承调度参数，内核启动时创建的任务使用默认值
+fn inherit_sched() -> SchedEntity {
+    try_current_task().map_or_else(Default::default, |task| task.sched.lock().fork())
+}
+
 // unsafe impl Sync for TaskControlBlock {}
 // unsafe impl Send for TaskControlBlock {}
 
@@ -116,78 +180,95 @@ pub enum TaskStatus {
 
 #[derive(Default)]
 pub struct SharedStatus {
+    /// 线程的待处理信号
     pub signals: Mutex<SignalFlags>,
+    /// 线程的信号屏蔽字
+    pub sigmask: Mutex<SignalFlags>,
+    pub usage: Usage,
     pub state: Mutex<TaskStatus>,
     pub exit_code: Mutex<Option<i32>>,
-}
-
-// 向用户栈压入参数，返回新的用户栈地址
-fn push_args(memory_set: &mut MemorySet, mut usp: VirtAddr, args: &str) -> VirtAddr {
-    let args_len = args.len();
-    // 8字节对齐
-    // let all_len = align_ceil::<u64>(args_len + size_of::<usize>());
-    let all_len = align_ceil(args_len, 8) as isize;
-    usp = usp.offset(-all_len);
-    unsafe {
-        // 向栈写入参数长度
-        // *translated_refmut(memory_set, usp as *const usize as *mut usize) = args_len;
-        // let args_addr = usp + size_of::<usize>();
-        let buffer = BufferHandle::new(translated_byte_buffer(memory_set, usp, args_len).unwrap());
-        // 写入参数
-        for (dst, src) in buffer.into_iter().zip(args.as_bytes().iter()) {
-            *dst = *src;
-        }
-    }
-    usp
+    /// 线程被 exec 或致命信号终止，返回用户态前退出
+    pub killed: AtomicBool,
 }
 
 impl TaskControlBlock {
     pub fn new(
         process: &Process,
         tid: usize,
-        entry: usize,
         ustack_base: usize,
-        args: &str,
+        info: &ElfLoadInfo,
+        args: &[String],
+        envs: &[String],
     ) -> Task {
         // let pid = pid_alloc();
         // // 添加内核栈
         // let (ksp_top, ksp_bottom) = kernel_stack_position(pid.id);
         let kstack = kstack_alloc();
-        let ustack = user_stack_addr(tid, ustack_base);
-        let memory_set = &mut process.inner.write().memory_set;
+        let stack_size = process.inner.read().rlimits.stack_size();
+        let ustack = user_stack_addr(tid, ustack_base, stack_size);
+        let space = process.inner.read().memory_set.clone();
+        let mut guard = space.write();
+        let memory_set = &mut *guard;
         ustack_alloc(memory_set, ustack.clone());
 
-        let usp = push_args(memory_set, ustack.end, args);
+        let usp = push_startup(memory_set, ustack.end, info, args, envs);
         // push_kernel_stack(ksp_top.into(), ksp_bottom.into());
-        let mut trap_cx = TrapContext::new(entry, usp.into(), kstack.bottom(), kernel_token());
+        let trap_cx = TrapContext::new(info.start, usp.into(), kstack.bottom(), kernel_token());
         // let trap_cx = unsafe { task.trap_context() };
 
-        unsafe {
-            trap_cx.set_args(OsStr::from_raw_parts_unchecked(
-                usize::from(usp) as *const u8,
-                args.len(),
-            ));
-        }
-
         // let (trap_cx_pa, task_cx) = build_user_context(&memory_set, trap_cx);
-        let context = Context::build(memory_set, trap_cx, trap_context_addr(tid).into());
+        let trap_slot = trap_context_alloc(memory_set);
+        let context = Context::build(memory_set, trap_cx, trap_context_addr(trap_slot).into());
         // drop(memory_set);
         Arc::new(Self {
             tid,
             shared: Default::default(),
             send_lock: AtomicU32::new(TASK_SEND_UNLOCK),
+            sched: Mutex::new(inherit_sched()),
             process: process.clone(),
             local: RefCell::new(ThreadLocal::new(
                 context,
                 kstack,
-                ustack.end,
+                Some(ustack.end),
+                trap_slot,
+                memory_set.token(),
+            )),
+        })
+    }
+
+    /// 内核线程在内核栈上运行 `entry`，不返回用户态，
+    /// trap 上下文只用于记录所在的硬件线程
+    pub fn new_kernel(
+        process: &Process,
+        memory_set: &Arc<RwLock<MemorySet>>,
+        tid: usize,
+        entry: KernelEntry,
+    ) -> Task {
+        let kstack = kstack_alloc();
+        let trap_cx = TrapContext::new(0, 0, kstack.bottom(), kernel_token());
+        let mut memory_set = memory_set.write();
+        let trap_slot = trap_context_alloc(&mut memory_set);
+        let mut context = Context::build(&memory_set, trap_cx, trap_context_addr(trap_slot).into());
+        let entry = Box::into_raw(Box::new(entry));
+        context.task_cx = TaskContext::kernel_thread(kstack.bottom(), entry as usize);
+        Arc::new(Self {
+            tid,
+            shared: Default::default(),
+            send_lock: AtomicU32::new(TASK_SEND_UNLOCK),
+            sched: Mutex::new(inherit_sched()),
+            process: process.clone(),
+            local: RefCell::new(ThreadLocal::new(
+                context,
+                kstack,
+                None,
+                trap_slot,
                 memory_set.token(),
             )),
         })
     }
 
     pub fn trap_context_va(&self) -> usize {
-        trap_context_addr(self.tid)
+        trap_context_addr(self.local.borrow().trap_slot)
     }
 
     // pub fn from_elf(elf: ElfFile, args: &str) -> Task {
@@ -216,34 +297,61 @@ impl TaskControlBlock {
     //     todo!()
     // }
 
-    pub fn fork(self: &Task, process: &Process) -> Task {
-        // let pid = pid_alloc();
-        // let (ksp_top, ksp_bottom) = kernel_stack_position(pid.id);
-        // add_kernel_stack(ksp_top.into(), ksp_bottom.into());
+    /// 复制调用线程作为 `process` 的线程 `tid`，新线程从系统调用返回0。
+    /// `stack` 不为0时作为新线程的栈指针，否则与调用者使用相同的栈
+    pub fn clone_task(
+        self: &Task,
+        process: &Process,
+        memory_set: &Arc<RwLock<MemorySet>>,
+        tid: usize,
+        flags: CloneFlags,
+        stack: usize,
+        tls: usize,
+    ) -> Task {
         let ksp = kstack_alloc();
-        // let memory_set = MemorySet::from_existed(&self.process.inner.read().memory_set);
         let mut trap_cx = unsafe { *self.trap_context() };
         // 设置初始内核栈，所以是安全的
         trap_cx.ksp = ksp.bottom();
-        // trap_cx.set_return(0);
-        let memory_set = &process.inner.read().memory_set;
-        let context = Context::build(memory_set, trap_cx, TRAP_CONTEXT.into());
-        let result = Arc::new(Self {
-            tid: self.tid,
-            shared: Default::default(),
+        trap_cx.set_return(0);
+        if stack != 0 {
+            trap_cx.reg_file.sp = stack;
+        }
+        if flags.contains(CloneFlags::CLONE_SETTLS) {
+            trap_cx.reg_file.tp = tls;
+        }
+        let mut memory_set = memory_set.write();
+        let trap_slot = trap_context_alloc(&mut memory_set);
+        let context = Context::build(&memory_set, trap_cx, trap_context_addr(trap_slot).into());
+        // 共享地址空间时用户栈属于调用者
+        let ustack = if flags.contains(CloneFlags::CLONE_VM) {
+            None
+        } else {
+            self.local.borrow().ustack
+        };
+        let mut local = ThreadLocal::new(context, ksp, ustack, trap_slot, memory_set.token());
+        // 与调用者同时运行且共享地址空间的线程不继承备用信号栈
+        if !flags.contains(CloneFlags::CLONE_VM) || flags.contains(CloneFlags::CLONE_VFORK) {
+            local.sigaltstack = self.local.borrow().sigaltstack;
+        }
+        Arc::new(Self {
+            tid,
+            shared: Arc::new(SharedStatus {
+                sigmask: Mutex::new(*self.shared.sigmask.lock()),
+                ..Default::default()
+            }),
             send_lock: AtomicU32::new(TASK_SEND_UNLOCK),
+            sched: Mutex::new(self.sched.lock().fork()),
             process: process.clone(),
-            local: RefCell::new(ThreadLocal::new(
-                context,
-                ksp,
-                self.local.borrow().ustack,
-                memory_set.token(),
-            )),
-        });
-        // result.process.inner.write().fd_table = self.process.inner.read().fd_table.clone();
-        // 初始化，安全
-        // unsafe { result.process.set_parent(&self.process) };
-        result
+            local: RefCell::new(local),
+        })
+    }
+
+    /// 线程退出或 exec 时清零 `clear_child_tid` 指向的 tid，写入失败时忽略
+    pub fn clear_child_tid(&self) {
+        let ptr = mem::take(&mut self.local.borrow_mut().clear_child_tid);
+        if ptr != 0 {
+            let _ = unsafe { copy_to_user(self.space(), ptr.into(), &0i32) };
+        }
     }
 
     pub fn is_ready(&self) -> bool {
@@ -251,15 +359,102 @@ impl TaskControlBlock {
     }
 
     pub fn exit(&self, code: i32) {
+        self.clear_child_tid();
         // let mut process_inner = self.process.inner.write();
         // 提前释放部分非共享数据
         // process_inner.fd_table.clear();
         // process_inner.tree.children.clear();
         self.process.remove_task(self.tid);
+        let killed = self.is_killed();
+        // 最后一个线程或未被终止的主线程退出则进程退出，内核进程不会退出
+        let last = self.process.inner.read().tasks.iter_elem().next().is_none();
+        if !self.process.is_kernel() && (last || (!killed && self.tid == 0)) {
+            let term_status = *self.process.shared.term_status.lock();
+            self.process
+                .exit(term_status.unwrap_or_else(|| exit_status(code)));
+        }
         // info!("App {} exit with code {code}", self.get_pid());
+        debug!(
+            "task {}:{} exit, migrations: {}",
+            self.process.get_pid(),
+            self.tid,
+            self.shared.usage.stat().migrations
+        );
         *self.shared.exit_code.lock() = Some(code);
         *self.shared.state.lock() = TaskStatus::Exited;
         // *self.state.lock() = TaskStatus::Exited;
+        // 通知等待线程退出的 exec 调用者和 join
+        wakeup_all();
+    }
+
+    /// 统计从上一次统计到现在的 CPU 时间，`user` 表示这段时间运行在用户态
+    pub fn account_time(&self, user: bool) {
+        let now = get_time();
+        let last = mem::replace(&mut self.local.borrow_mut().timestamp, now);
+        let ticks = now.saturating_sub(last);
+        self.shared.usage.add_time(user, ticks);
+        self.process.shared.usage.add_time(user, ticks);
+    }
+
+    /// 统计上下文切换，因阻塞让出处理器为主动切换
+    pub fn account_switch(&self, voluntary: bool) {
+        self.shared.usage.add_switch(voluntary);
+        self.process.shared.usage.add_switch(voluntary);
+    }
+
+    /// 统计在硬件线程之间的迁移
+    pub fn account_migration(&self) {
+        self.shared.usage.add_migration();
+        self.process.shared.usage.add_migration();
+    }
+
+    /// 重新开始统计，等待运行的时间不计入 CPU 时间
+    pub fn resume_time(&self) {
+        self.local.borrow_mut().timestamp = get_time();
+    }
+
+    pub fn kill(&self) {
+        self.shared.killed.store(true, Ordering::Release);
+    }
+
+    /// 全局唯一的线程 id，进程的第一个线程和 exec 后的线程与进程的 pid 相同
+    pub fn gettid(&self) -> isize {
+        self.local
+            .borrow()
+            .thread_id
+            .as_ref()
+            .map_or_else(|| self.process.get_pid(), |tid| tid.id)
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.shared.killed.load(Ordering::Acquire)
+    }
+
+    /// 在新的地址空间中重建当前线程的用户栈和 trap 上下文，由调用者替换进程的地址空间，
+    /// 返回原地址空间中的 trap 上下文槽位
+    pub fn exec(
+        &self,
+        memory_set: &mut MemorySet,
+        info: &ElfLoadInfo,
+        args: &[String],
+        envs: &[String],
+    ) -> usize {
+        let stack_size = self.process.inner.read().rlimits.stack_size();
+        let ustack = user_stack_addr(self.tid, info.ustack_base, stack_size);
+        ustack_alloc(memory_set, ustack.clone());
+        let usp = push_startup(memory_set, ustack.end, info, args, envs);
+        let hartid = unsafe { self.trap_context().hartid };
+        let mut local = self.local.borrow_mut();
+        let mut trap_cx =
+            TrapContext::new(info.start, usp.into(), local._ksp.bottom(), kernel_token());
+        trap_cx.hartid = hartid;
+        let trap_slot = trap_context_alloc(memory_set);
+        local.context = Context::build(memory_set, trap_cx, trap_context_addr(trap_slot).into());
+        local.ustack = Some(ustack.end);
+        local.token = memory_set.token();
+        local.fault = None;
+        local.sigaltstack = SignalStack::disabled();
+        mem::replace(&mut local.trap_slot, trap_slot)
     }
 
     pub fn set_state(&self, state: TaskStatus) {
@@ -281,7 +476,7 @@ impl TaskControlBlock {
     #[inline]
     /// 不是线程安全的
     pub unsafe fn space(&self) -> &mut MemorySet {
-        unsafe { &mut (*self.process.inner.as_mut_ptr()).memory_set }
+        unsafe { &mut *(*self.process.inner.as_mut_ptr()).memory_set.as_mut_ptr() }
     }
     pub fn token(&self) -> usize {
         unsafe { (*self.local.as_ptr()).token }
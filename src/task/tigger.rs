@@ -2,16 +2,19 @@ use core::task::Poll;
 
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
 
-use crate::timer::get_time_ms;
+use crate::timer::{add_timer, get_time, ms_to_ticks, TimerHandle};
 
 use super::{
-    process::{Process, ProcessSharedStatus, ProcessStatus},
+    process::{Process, ProcessSharedStatus, ProcessStatus, WaitOptions, CONTINUED_STATUS},
+    scheduler::get_processor,
     signal::SignalFlags,
-    tcb::{SharedStatus, Task},
+    tcb::{SharedStatus, Task, TaskStatus},
 };
 
 pub type FutureBox = Box<dyn Future<Output = ()> + Send + Sync + 'static>;
 
+/// 阻塞任务的等待条件。处理器不会周期性地轮询等待队列，
+/// 改变等待条件的一方必须调用 `wakeup_all`，或者由定时器通知
 pub trait Future {
     type Output;
     fn poll(&self) -> Poll<Self::Output>;
@@ -46,14 +49,25 @@ where
 }
 
 pub struct Timer {
-    expire_time: usize,
+    deadline: usize,
+    handle: TimerHandle,
 }
 
 impl Timer {
-    pub fn new(time: usize) -> Self {
-        Self {
-            expire_time: time + get_time_ms(),
-        }
+    pub fn new(ms: usize) -> Self {
+        Self::until(get_time().saturating_add(ms_to_ticks(ms)))
+    }
+
+    /// 在时钟周期 `deadline` 到期，到期时通知当前处理器轮询等待队列
+    pub fn until(deadline: usize) -> Self {
+        let handle = add_timer(deadline, 0, Arc::new(|| get_processor().notify_wait()));
+        Self { deadline, handle }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.handle.cancel();
     }
 }
 
@@ -61,7 +75,7 @@ impl Future for Timer {
     type Output = ();
 
     fn poll(&self) -> Poll<Self::Output> {
-        if get_time_ms() >= self.expire_time {
+        if get_time() >= self.deadline {
             Poll::Ready(())
         } else {
             Poll::Pending
@@ -69,23 +83,36 @@ impl Future for Timer {
     }
 }
 
-pub struct TaskWaiter {
-    shared_data: Arc<ProcessSharedStatus>,
+/// 等待任意一个子进程退出，或按 `options` 停止、继续运行
+pub struct ChildrenWaiter {
+    shared_datas: Vec<Arc<ProcessSharedStatus>>,
+    options: WaitOptions,
 }
 
-impl TaskWaiter {
-    pub fn new(process: &Process) -> Self {
+impl ChildrenWaiter {
+    pub fn new(children: &[Process], options: WaitOptions) -> Self {
         Self {
-            shared_data: process.shared.clone(),
+            shared_datas: children.iter().map(|child| child.shared.clone()).collect(),
+            options,
         }
     }
 }
 
-impl Future for TaskWaiter {
+impl Future for ChildrenWaiter {
     type Output = ();
 
     fn poll(&self) -> Poll<Self::Output> {
-        if let ProcessStatus::Exit(_) = *self.shared_data.state.lock() {
+        let ready = self.shared_datas.iter().any(|shared| {
+            if matches!(*shared.state.lock(), ProcessStatus::Zombie(_)) {
+                return true;
+            }
+            match *shared.wait_event.lock() {
+                Some(CONTINUED_STATUS) => self.options.contains(WaitOptions::WCONTINUED),
+                Some(_) => self.options.contains(WaitOptions::WUNTRACED),
+                None => false,
+            }
+        });
+        if self.shared_datas.is_empty() || ready {
             Poll::Ready(())
         } else {
             Poll::Pending
@@ -93,30 +120,25 @@ impl Future for TaskWaiter {
     }
 }
 
-pub struct ChildrenWaiter {
-    shared_datas: Vec<Arc<ProcessSharedStatus>>,
+/// 等待一组线程全部退出
+pub struct ThreadsWaiter {
+    shared_datas: Vec<Arc<SharedStatus>>,
 }
 
-impl ChildrenWaiter {
-    pub fn new(parent: &Process) -> Self {
-        let children = &parent.inner.read().tree.children;
-        let mut shared_datas = Vec::with_capacity(children.len());
-        for child in children {
-            shared_datas.push(child.shared.clone());
-        }
+impl ThreadsWaiter {
+    pub fn new(shared_datas: Vec<Arc<SharedStatus>>) -> Self {
         Self { shared_datas }
     }
 }
 
-impl Future for ChildrenWaiter {
+impl Future for ThreadsWaiter {
     type Output = ();
 
     fn poll(&self) -> Poll<Self::Output> {
-        if self.shared_datas.is_empty()
-            || self
-                .shared_datas
-                .iter()
-                .any(|state| matches!(*state.state.lock(), ProcessStatus::Exit(_)))
+        if self
+            .shared_datas
+            .iter()
+            .all(|shared| *shared.state.lock() == TaskStatus::Exited)
         {
             Poll::Ready(())
         } else {
@@ -125,6 +147,38 @@ impl Future for ChildrenWaiter {
     }
 }
 
+/// 等待条件满足，或者线程收到未被屏蔽的信号
+pub struct Interruptible<F> {
+    inner: F,
+    shared: Arc<SharedStatus>,
+    process: Arc<ProcessSharedStatus>,
+}
+
+impl<F> Interruptible<F> {
+    pub fn new(task: &Task, inner: F) -> Self {
+        Self {
+            inner,
+            shared: task.shared.clone(),
+            process: task.process.shared.clone(),
+        }
+    }
+}
+
+impl<F> Future for Interruptible<F>
+where
+    F: Future<Output = ()>,
+{
+    type Output = ();
+
+    fn poll(&self) -> Poll<Self::Output> {
+        if self.shared.has_signal(&self.process) {
+            Poll::Ready(())
+        } else {
+            self.inner.poll()
+        }
+    }
+}
+
 pub struct SignalWaiter {
     flag: SignalFlags,
     shared_data: Arc<SharedStatus>,
@@ -0,0 +1,79 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 资源使用统计，线程和进程各有一份，时间单位为时钟周期
+#[derive(Debug, Default)]
+pub struct Usage {
+    utime: AtomicUsize,
+    stime: AtomicUsize,
+    /// 因阻塞让出处理器的次数
+    nvcsw: AtomicUsize,
+    /// 被抢占或主动 yield 的次数
+    nivcsw: AtomicUsize,
+    /// 在硬件线程之间迁移的次数
+    migrations: AtomicUsize,
+    /// 最大的驻留内存，单位为字节
+    maxrss: AtomicUsize,
+}
+
+/// 某一时刻的资源使用统计
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UsageStat {
+    pub utime: usize,
+    pub stime: usize,
+    pub nvcsw: usize,
+    pub nivcsw: usize,
+    pub migrations: usize,
+    pub maxrss: usize,
+}
+
+impl Usage {
+    pub fn add_time(&self, user: bool, ticks: usize) {
+        let time = if user { &self.utime } else { &self.stime };
+        time.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    /// 用户态和内核态时间
+    pub fn times(&self) -> (usize, usize) {
+        (
+            self.utime.load(Ordering::Relaxed),
+            self.stime.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn add_switch(&self, voluntary: bool) {
+        let count = if voluntary { &self.nvcsw } else { &self.nivcsw };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_migration(&self) {
+        self.migrations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn update_rss(&self, size: usize) {
+        self.maxrss.fetch_max(size, Ordering::Relaxed);
+    }
+
+    pub fn stat(&self) -> UsageStat {
+        let (utime, stime) = self.times();
+        UsageStat {
+            utime,
+            stime,
+            nvcsw: self.nvcsw.load(Ordering::Relaxed),
+            nivcsw: self.nivcsw.load(Ordering::Relaxed),
+            migrations: self.migrations.load(Ordering::Relaxed),
+            maxrss: self.maxrss.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl UsageStat {
+    /// 累加已回收子进程的统计，`maxrss` 取最大值
+    pub fn merge(&mut self, other: &UsageStat) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+        self.migrations += other.migrations;
+        self.maxrss = self.maxrss.max(other.maxrss);
+    }
+}
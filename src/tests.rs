@@ -3,6 +3,8 @@ use crate::mm::{
     heap_allocator::heap_test,
     memory_set::{framed_map_test, identical_map_test},
 };
+use crate::task::{kthread::kthread_test, policy::policy_test};
+use crate::timer::timer_queue_test;
 
 #[cfg(test)]
 fn tests() {
@@ -13,9 +15,14 @@ fn tests() {
     // mm
     identical_map_test();
     framed_map_test();
+    // timer
+    timer_queue_test();
+    // sched policy
+    policy_test();
+    // kthread
+    kthread_test();
 }
 
-
 #[cfg(test)]
 pub fn test_runner(tests: &[&dyn Fn()]) {
     println!("Running {} tests", tests.len());
@@ -1,10 +1,71 @@
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use riscv::register::time;
+use spin::Mutex;
 
 use crate::{
-    config::{CLOCK_FREQ, TICK_FREQ},
-    sbi::set_timer,
+    config::{CLOCK_FREQ, NUM_HARTS, TICK_FREQ},
+    sbi::{get_hartid, set_timer},
 };
 
+pub const MSEC_PER_SEC: usize = 1000;
+pub const NSEC_PER_SEC: usize = 1_000_000_000;
+/// 调度时钟间隔（时钟周期）
+const TICK_INTERVAL: usize = CLOCK_FREQ / TICK_FREQ;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeSpec {
+    pub tv_sec: usize,
+    pub tv_nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn from_ns(ns: usize) -> Self {
+        Self {
+            tv_sec: ns / NSEC_PER_SEC,
+            tv_nsec: ns % NSEC_PER_SEC,
+        }
+    }
+
+    pub fn as_ns(&self) -> usize {
+        self.tv_sec
+            .saturating_mul(NSEC_PER_SEC)
+            .saturating_add(self.tv_nsec)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.tv_nsec < NSEC_PER_SEC
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeVal {
+    pub tv_sec: usize,
+    pub tv_usec: usize,
+}
+
+impl TimeVal {
+    pub fn from_ticks(ticks: usize) -> Self {
+        let us = ticks_to_ns(ticks) / 1000;
+        Self {
+            tv_sec: us / 1_000_000,
+            tv_usec: us % 1_000_000,
+        }
+    }
+
+    pub fn as_ns(&self) -> usize {
+        self.tv_sec
+            .saturating_mul(NSEC_PER_SEC)
+            .saturating_add(self.tv_usec.saturating_mul(1000))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.tv_usec < 1_000_000
+    }
+}
+
 #[inline]
 pub fn get_time() -> usize {
     time::read()
@@ -12,10 +73,218 @@ pub fn get_time() -> usize {
 
 #[inline]
 pub fn get_time_ms() -> usize {
-    time::read() / (CLOCK_FREQ / 1000)
+    time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
 #[inline]
+pub fn get_time_ns() -> usize {
+    ticks_to_ns(time::read())
+}
+
+/// 纳秒转换为时钟周期，向上取整以保证定时器不会提前到期
+pub const fn ns_to_ticks(ns: usize) -> usize {
+    let ticks = (ns as u128 * CLOCK_FREQ as u128).div_ceil(NSEC_PER_SEC as u128);
+    if ticks > usize::MAX as u128 {
+        usize::MAX
+    } else {
+        ticks as usize
+    }
+}
+
+pub const fn ticks_to_ns(ticks: usize) -> usize {
+    (ticks as u128 * NSEC_PER_SEC as u128 / CLOCK_FREQ as u128) as usize
+}
+
+pub const fn ms_to_ticks(ms: usize) -> usize {
+    ms.saturating_mul(CLOCK_FREQ / MSEC_PER_SEC)
+}
+
+pub type TimerCallback = Arc<dyn Fn() + Send + Sync>;
+
+struct TimerEvent {
+    /// 周期定时器的间隔，为0表示单次定时器
+    period: usize,
+    callback: TimerCallback,
+}
+
+/// 每个硬件线程的定时器队列
+struct HartTimer {
+    /// 按 (到期时间, id) 排序的定时器
+    events: BTreeMap<(usize, usize), TimerEvent>,
+    /// id 到到期时间的索引，用于取消定时器
+    deadlines: BTreeMap<usize, usize>,
+    /// 下一次调度时钟的时间
+    next_tick: usize,
+}
+
+impl HartTimer {
+    const fn new() -> Self {
+        Self {
+            events: BTreeMap::new(),
+            deadlines: BTreeMap::new(),
+            next_tick: usize::MAX,
+        }
+    }
+
+    fn insert(&mut self, deadline: usize, id: usize, event: TimerEvent) {
+        self.events.insert((deadline, id), event);
+        self.deadlines.insert(id, deadline);
+    }
+
+    fn remove(&mut self, id: usize) -> Option<TimerEvent> {
+        let deadline = self.deadlines.remove(&id)?;
+        self.events.remove(&(deadline, id))
+    }
+
+    fn next_deadline(&self) -> usize {
+        self.events
+            .first_key_value()
+            .map_or(usize::MAX, |(&(deadline, _), _)| deadline)
+            .min(self.next_tick)
+    }
+
+    /// 按最近的到期时间设置下一次时钟中断
+    fn program(&self) {
+        set_timer(self.next_deadline());
+    }
+
+    /// 弹出一个到期的定时器，周期定时器会被重新加入队列
+    fn pop_expired(&mut self, now: usize) -> Option<TimerCallback> {
+        let (&(deadline, id), _) = self.events.first_key_value()?;
+        if deadline > now {
+            return None;
+        }
+        let event = self.remove(id).unwrap();
+        if event.period != 0 {
+            // 跳过已经错过的周期
+            let missed = (now - deadline) / event.period;
+            let next = deadline + (missed + 1) * event.period;
+            self.insert(
+                next,
+                id,
+                TimerEvent {
+                    period: event.period,
+                    callback: event.callback.clone(),
+                },
+            );
+        }
+        Some(event.callback)
+    }
+}
+
+static HART_TIMERS: [Mutex<HartTimer>; NUM_HARTS] =
+    [const { Mutex::new(HartTimer::new()) }; NUM_HARTS];
+
+static TIMER_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    hartid: usize,
+    id: usize,
+}
+
+impl TimerHandle {
+    /// 取消定时器，如果定时器已经到期（且不是周期定时器）则返回 `false`
+    pub fn cancel(&self) -> bool {
+        HART_TIMERS[self.hartid].lock().remove(self.id).is_some()
+    }
+}
+
+/// 在当前硬件线程上添加一个在时钟周期 `deadline` 到期的定时器，
+/// `period` 不为0时为周期定时器。回调函数在时钟中断中执行
+pub fn add_timer(deadline: usize, period: usize, callback: TimerCallback) -> TimerHandle {
+    let hartid = get_hartid();
+    let id = TIMER_ID.fetch_add(1, Ordering::Relaxed);
+    let mut timers = HART_TIMERS[hartid].lock();
+    timers.insert(deadline, id, TimerEvent { period, callback });
+    timers.program();
+    TimerHandle { hartid, id }
+}
+
+/// 重置当前硬件线程的调度时钟
 pub fn set_next_trigger() {
-    set_timer(time::read() + CLOCK_FREQ / TICK_FREQ);
+    let mut timers = HART_TIMERS[get_hartid()].lock();
+    timers.next_tick = get_time() + TICK_INTERVAL;
+    timers.program();
+}
+
+/// 开启或关闭当前硬件线程的调度时钟，关闭后只在定时器到期时产生时钟中断
+pub fn set_tick(enabled: bool) {
+    let mut timers = HART_TIMERS[get_hartid()].lock();
+    let ticking = timers.next_tick != usize::MAX;
+    if enabled != ticking {
+        timers.next_tick = if enabled {
+            get_time() + TICK_INTERVAL
+        } else {
+            usize::MAX
+        };
+        timers.program();
+    }
+}
+
+/// 处理时钟中断：执行所有到期的定时器并设置下一次时钟中断，
+/// 返回是否到达调度时钟
+pub fn handle_timer_interrupt() -> bool {
+    let timers = &HART_TIMERS[get_hartid()];
+    let now = get_time();
+    // 回调执行时不能持有锁，回调中可能会添加新的定时器
+    loop {
+        let expired = timers.lock().pop_expired(now);
+        match expired {
+            Some(callback) => callback(),
+            None => break,
+        }
+    }
+    let mut timers = timers.lock();
+    let tick = now >= timers.next_tick;
+    // 调度时钟关闭时 `next_tick` 为 `usize::MAX`
+    if tick {
+        timers.next_tick = now + TICK_INTERVAL;
+    }
+    timers.program();
+    tick
+}
+
+#[cfg(feature = "debug")]
+pub fn timer_queue_test() {
+    use crate::tools::ansi::{Color, Colour};
+
+    let fired = Arc::new(AtomicUsize::new(0));
+    let mut timers = HartTimer::new();
+    for (id, deadline) in [30, 10, 20].into_iter().enumerate() {
+        let fired = fired.clone();
+        let callback: TimerCallback = Arc::new(move || {
+            fired.fetch_add(deadline, Ordering::Relaxed);
+        });
+        timers.insert(
+            deadline,
+            id,
+            TimerEvent {
+                period: 0,
+                callback,
+            },
+        );
+    }
+    assert_eq!(timers.next_deadline(), 10);
+    // 取消到期时间为20的定时器
+    assert!(timers.remove(2).is_some());
+    assert!(timers.remove(2).is_none());
+    while let Some(callback) = timers.pop_expired(25) {
+        callback();
+    }
+    assert_eq!(fired.load(Ordering::Relaxed), 10);
+    assert_eq!(timers.next_deadline(), 30);
+    // 周期定时器到期后重新入队
+    timers.insert(
+        40,
+        3,
+        TimerEvent {
+            period: 10,
+            callback: Arc::new(|| ()),
+        },
+    );
+    assert!(timers.pop_expired(55).is_some());
+    assert!(timers.pop_expired(55).is_some());
+    assert_eq!(timers.deadlines.get(&3), Some(&60));
+    println!("[{}] timer_queue_test", "passed".dye(Color::GreenB));
 }
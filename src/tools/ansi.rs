@@ -1,6 +1,6 @@
 #![allow(unused)]
 
-use core::fmt::{self, Arguments, Debug, Display};
+use core::fmt::{self, Debug, Display};
 
 use alloc::{format, string::String};
 
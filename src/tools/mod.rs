@@ -86,6 +86,9 @@ impl<T> Table<T> {
             None
         }
     }
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.inner.get_mut(idx).and_then(|val| val.as_mut())
+    }
     pub fn get_entry(&mut self, idx: usize) -> &mut Option<T> {
         self.inner.get_mut(idx).unwrap()
     }
@@ -1,5 +1,4 @@
 use super::trap_handler;
-use os_tools::OsStr;
 use riscv::register::sstatus::{self, Sstatus, SPP};
 
 #[derive(Debug, Clone, Copy)]
@@ -62,9 +61,4 @@ impl TrapContext {
         let a = &self.reg_file.a;
         [a[0], a[1], a[2], a[3], a[4], a[5]]
     }
-
-    pub fn set_args(&mut self, args: OsStr<'static>) {
-        self.reg_file.a[0] = args.as_ptr() as usize;
-        self.reg_file.a[1] = args.len();
-    }
 }
@@ -5,14 +5,21 @@ use log::warn;
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, sstatus, stval, stvec,
+    sie, sip, sstatus, stval, stvec,
 };
 
 use crate::{
     config::TRAMPOLINE,
-    syscall::Syscall,
-    task::{processor::Schedule, scheduler::get_processor, signal::SignalHandle},
-    timer::set_next_trigger,
+    drivers::plic::handle_external,
+    ipi::handle_ipi,
+    sbi::get_hartid,
+    syscall::{errno::ERESTARTSYS, Syscall, SYSCALL_SIGRETURN},
+    task::{
+        processor::{Current, Schedule},
+        scheduler::get_processor,
+        signal::{SignalFlags, SignalHandle},
+    },
+    timer::{handle_timer_interrupt, set_next_trigger},
 };
 
 use self::context::TrapContext;
@@ -27,55 +34,176 @@ pub unsafe fn set_kernel_trap_entry() {
     stvec::write(kernel_trap_entry as usize, TrapMode::Direct);
 }
 
+/// 内核初始化和调度时关中断，进入系统调用或内核线程后开中断
 pub fn init() {
     unsafe {
         set_kernel_trap_entry();
         sstatus::clear_sie();
-        sie::set_stimer();
     }
+    unmask_interrupts();
     set_next_trigger();
 }
 
+/// 当前任务的 trap 上下文，exec 后会发生改变
+unsafe fn current_trap_context<T: Schedule>(proc: &T) -> &'static mut TrapContext {
+    let task = proc.current_task();
+    &mut *(task.trap_context() as *const _ as *mut TrapContext)
+}
+
 /// 该函数内的强引用可能需要手动释放
 pub unsafe extern "C" fn trap_handler() -> ! {
     set_kernel_trap_entry();
-    let proc = get_processor();
+    let proc = Current;
     let task = proc.current_task();
-    let trap_cx_va = task.trap_context_va();
-    let cx = &mut *(task.trap_context() as *const _ as *mut TrapContext);
-    let satp = task.token();
+    task.account_time(true);
+    task.process.check_cpu_timers();
     drop(task);
+    // 内核态的中断被推迟到安全点或返回用户态前处理
+    sstatus::set_sie();
+    // 请求重新执行的系统调用的第一个参数
+    let mut interrupted = None;
     match scause::read().cause() {
         Trap::Exception(Exception::UserEnvCall) => {
+            let cx = current_trap_context(&proc);
             cx.sepc += 4;
-            let result = proc.syscall(cx.syscall_id(), cx.syscall_args());
-            cx.set_return(result as usize);
+            let (id, args) = (cx.syscall_id(), cx.syscall_args());
+            let result = proc.syscall(id, args);
+            current_trap_context(&proc).set_return(result as usize);
+            // `sigreturn` 恢复的返回值来自用户栈，不是重新执行的请求
+            if result == -ERESTARTSYS.0 && id != SYSCALL_SIGRETURN {
+                interrupted = Some(args[0]);
+            }
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            proc.yield_();
-            set_next_trigger();
+            let tick = handle_timer_interrupt();
+            get_processor().on_tick(tick);
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            handle_ipi();
+            // 被唤醒的任务可能需要抢占当前任务
+            get_processor().on_tick(false);
         }
-        Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::StorePageFault) => {
+        Trap::Interrupt(Interrupt::SupervisorExternal) => handle_external(get_hartid()),
+        Trap::Exception(
+            Exception::StoreFault
+            | Exception::StorePageFault
+            | Exception::LoadFault
+            | Exception::LoadPageFault
+            | Exception::InstructionFault
+            | Exception::InstructionPageFault,
+        ) => {
             warn!("PageFault[{:#x}]", stval::read());
-            proc.exit_current(1);
+            proc.current_task()
+                .force_signal(SignalFlags::SIGSEGV, stval::read());
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             warn!("IllegalInstruction[{:#x}]", stval::read());
-            proc.exit_current(1);
+            proc.current_task()
+                .force_signal(SignalFlags::SIGILL, stval::read());
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            proc.current_task()
+                .force_signal(SignalFlags::SIGTRAP, stval::read());
+        }
+        Trap::Exception(Exception::InstructionMisaligned | Exception::StoreMisaligned) => {
+            warn!("Misaligned[{:#x}]", stval::read());
+            proc.current_task()
+                .force_signal(SignalFlags::SIGBUS, stval::read());
         }
         trap => {
             panic!("Unsupported trap {:?}, stval = {:#x}!", trap, stval::read());
         }
     }
-    proc.handle_signals();
+    proc.handle_signals(interrupted);
+    // 被 exec 或信号终止的线程不再返回用户态
+    if proc.current_task().is_killed() {
+        proc.exit_current(0);
+    }
+    let task = proc.current_task();
+    task.account_time(false);
+    let (satp, trap_cx_va) = (task.token(), task.trap_context_va());
+    drop(task);
+    // 切换 trap 入口前关中断，被推迟的中断在用户态立即触发
+    sstatus::clear_sie();
+    unmask_interrupts();
     unsafe { user_trap_return(satp, trap_cx_va) }
 }
 
+/// 重新允许被内核态 trap 屏蔽的中断
+#[inline]
+pub fn unmask_interrupts() {
+    unsafe {
+        sie::set_stimer();
+        sie::set_ssoft();
+        sie::set_sext();
+    }
+}
+
+/// 在关中断的安全点处理被推迟的中断，`preempt` 时与从用户态陷入时的处理相同，
+/// 调度时即将切换任务，只执行到期的定时器和处理器间中断
+pub unsafe fn handle_deferred_interrupts(preempt: bool) {
+    let pending = sip::read();
+    unmask_interrupts();
+    if pending.stimer() {
+        let tick = handle_timer_interrupt();
+        if preempt {
+            get_processor().on_tick(tick);
+        }
+    }
+    if pending.ssoft() {
+        handle_ipi();
+        if preempt {
+            get_processor().on_tick(false);
+        }
+    }
+    if pending.sext() {
+        handle_external(get_hartid());
+    }
+}
+
+/// 内核态的 trap 可能打断持有锁的代码，只屏蔽中断源，
+/// 中断保持待处理状态直到安全点
+extern "C" fn kernel_trap_handler() {
+    match scause::read().cause() {
+        Trap::Interrupt(Interrupt::SupervisorTimer) => unsafe { sie::clear_stimer() },
+        Trap::Interrupt(Interrupt::SupervisorSoft) => unsafe { sie::clear_ssoft() },
+        Trap::Interrupt(Interrupt::SupervisorExternal) => unsafe { sie::clear_sext() },
+        trap => {
+            use riscv::register::sepc;
+            println!("stval = {:#x}, sepc = {:#x}", stval::read(), sepc::read());
+            panic!("a trap {:?} from kernel!", trap);
+        }
+    }
+}
+
+/// 在内核栈上保存调用者保存的寄存器，被调用者保存的寄存器由处理函数维护
+#[naked]
 #[repr(align(4))]
-pub unsafe fn kernel_trap_entry() {
-    use riscv::register::sepc;
-    println!("stval = {:#x}, sepc = {:#x}", stval::read(), sepc::read());
-    panic!("a trap {:?} from kernel!", scause::read().cause());
+pub unsafe extern "C" fn kernel_trap_entry() {
+    naked_asm! {r"
+        .altmacro
+        addi sp, sp, -18*8
+        sd ra, 0*8(sp)
+        STORES t, 7, 1
+        STORES a, 8, 8
+        csrr t0, sepc
+        csrr t1, sstatus
+        sd t0, 16*8(sp)
+        sd t1, 17*8(sp)
+        call {handler}
+        ld t0, 16*8(sp)
+        ld t1, 17*8(sp)
+        csrw sepc, t0
+        csrw sstatus, t1
+        ld ra, 0*8(sp)
+        LOADS t, 7, 1
+        LOADS a, 8, 8
+        addi sp, sp, 18*8
+        sret
+        ",
+        handler = sym kernel_trap_handler,
+        options()
+    }
 }
 
 // 定义从栈上保存或恢复寄存器的汇编宏